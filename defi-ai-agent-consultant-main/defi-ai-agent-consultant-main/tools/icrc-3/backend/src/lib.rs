@@ -1,10 +1,14 @@
-use candid::{Nat, Principal};
+use candid::{Int, Nat, Principal};
+use ic_cdk::api::management_canister::main::{canister_info, CanisterInfoRequest};
 use ic_cdk::api::time;
 use ic_cdk_macros::*;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
 use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
 use num_traits::cast::ToPrimitive;
+use num_traits::Zero;
 
 mod types;
 use types::*;
@@ -36,6 +40,129 @@ thread_local! {
         )
     );
 
+    static ACCOUNT_LABELS: RefCell<StableBTreeMap<Account, AccountLabel, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+        )
+    );
+
+    static APPROVED_AMOUNTS: RefCell<StableBTreeMap<AccountPair, StableNat, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+        )
+    );
+
+    static FEES_PAID: RefCell<StableBTreeMap<Account, StableNat, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+        )
+    );
+
+    // Accounts that consented to dust consolidation, with the time they opted in
+    static DUST_OPT_INS: RefCell<StableBTreeMap<Account, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+        )
+    );
+
+    // Spending caps granted by an account to a delegate, keyed by (owner account, delegate default account)
+    static DELEGATIONS: RefCell<StableBTreeMap<AccountPair, Allowance, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+        )
+    );
+
+    // KYC tier assigned to each account; accounts without an entry are tier 0 (most restrictive)
+    static KYC_TIERS: RefCell<StableBTreeMap<Account, u8, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+        )
+    );
+
+    // Outstanding and paid payment requests by code
+    static PAYMENT_REQUESTS: RefCell<StableBTreeMap<PaymentCode, PaymentRequest, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+        )
+    );
+
+    // Pending scheduled transfers keyed by (execute_at, schedule id) so due transfers sort first
+    static SCHEDULED_TRANSFERS: RefCell<StableBTreeMap<(u64, ScheduleId), ScheduledTransfer, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        )
+    );
+
+    // Index of the most recent approve block for each (owner, spender) pair
+    static LAST_APPROVE_BLOCKS: RefCell<StableBTreeMap<AccountPair, StableBlockIndex, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        )
+    );
+
+    // Timestamp of the latest block involving each account
+    static LAST_ACTIVITY: RefCell<StableBTreeMap<Account, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        )
+    );
+
+    // Transfers held for the dispute window, keyed by their transfer block index
+    static PENDING_TRANSFERS: RefCell<StableBTreeMap<StableBlockIndex, PendingTransfer, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        )
+    );
+
+    // Transactions submitted with a created_at_time, mapped to their block index for deduplication
+    static RECENT_TRANSACTIONS: RefCell<StableBTreeMap<DedupKey, StableBlockIndex, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        )
+    );
+
+    // Update methods switched off by a controller; they fail with TemporarilyUnavailable
+    static DISABLED_METHODS: RefCell<StableBTreeMap<MethodName, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        )
+    );
+
+    // Fees accrued by deferred-fee transfers and not yet settled
+    static FEE_DEBTS: RefCell<StableBTreeMap<Account, StableNat, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        )
+    );
+
+    // Open escrows, keyed by the index of the block that locked their funds
+    static ESCROWS: RefCell<StableBTreeMap<EscrowId, Escrow, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        )
+    );
+
+    // (account, block) for every account each block references, for per-account history
+    static ACCOUNT_BLOCKS: RefCell<StableBTreeMap<AccountBlock, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        )
+    );
+
+    // Registered aliases and the account each resolves to
+    static ALIASES: RefCell<StableBTreeMap<Alias, Account, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        )
+    );
+
+    // Anti-fraud reputation scores set by controllers
+    static REPUTATIONS: RefCell<StableBTreeMap<Account, Reputation, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        )
+    );
+
     static TOKEN_DATA: RefCell<TokenData> = RefCell::new(TokenData {
         name: "ICRC3 Token".to_string(),
         symbol: "ICR3".to_string(),
@@ -47,12 +174,74 @@ thread_local! {
             subaccount: None,
         }),
         next_block_index: Nat::from(0u64),
+        approvals_paused: false,
+        reject_fee_exceeding_amount: false,
+        treasury_account: None,
+        treasury_bps: 0,
+        max_subaccounts_per_principal: None,
+        tag_revocations: false,
+        min_approval_amount: None,
+        fee_oracle: None,
+        max_fee_change_bps: 0,
+        dust_consolidation_account: None,
+        dust_threshold: Nat::from(0u64),
+        delegations_enabled: false,
+        kyc_tier_limits: Vec::new(),
+        payment_request_ttl: None,
+        next_payment_code: 0,
+        minting_disabled: false,
+        scheduled_transfers_enabled: false,
+        next_schedule_id: 0,
+        skip_unchanged_approvals: false,
+        mint_fee: None,
+        dispute_window: None,
+        require_approval_expiry: None,
+        fee_debt_cap: None,
+        last_block_hash: None,
+        escrow_ttl: None,
+        tip_account: None,
+        aliases_enabled: None,
+        fee_collector: None,
+        default_approval_expiry: None,
+        min_reputation: None,
+        max_blocks_per_request: None,
+        largest_transaction: None,
+        batch_netting_enabled: None,
+        max_supply: None,
+        daily_summaries_enabled: None,
+        daily_totals: None,
+        min_burn_amount: None,
+        // A fresh ledger only ever stores canonical accounts
+        default_subaccounts_merged: Some(true),
+        // ...and indexes every block it records
+        account_blocks_backfilled: Some(true),
     });
+
+    // Copy of TOKEN_DATA written before an upgrade and restored after it
+    static TOKEN_DATA_CELL: RefCell<StableCell<TokenData, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+            TOKEN_DATA.with(|data| data.borrow().clone()),
+        ).expect("failed to initialize the token data cell")
+    );
+
+    // Hash of the running wasm module, fetched once per install or upgrade from the management canister
+    static MODULE_HASH: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+
+    // Last allowance pair examined by the expired-allowance sweep (not persisted; restarts from the beginning)
+    static ALLOWANCE_SWEEP_CURSOR: RefCell<Option<AccountPair>> = const { RefCell::new(None) };
 }
 
 // Token Constants
 const DEFAULT_SUBACCOUNT: Option<Subaccount> = None;
 const TX_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours in nanoseconds
+const MAX_PAGE_SIZE: u32 = 1_000; // Upper bound on entries returned by paginated queries
+const MAX_SCAN_BLOCKS: u64 = 10_000; // Upper bound on blocks scanned by analytics queries
+const MAX_BATCH_SIZE: usize = 100; // Upper bound on entries processed by batch updates
+const SELF_CHECK_BLOCKS: u64 = 100; // Blocks at the tail of the log re-hashed by icrc3_self_check
+const FEE_ORACLE_INTERVAL: u64 = 10 * 60 * 1_000_000_000; // 10 minutes in nanoseconds
+const MODULE_HASH_RETRY_INTERVAL: u64 = 60 * 1_000_000_000; // 1 minute in nanoseconds
+const BACKFILL_CHUNK_SIZE: u64 = 1_000; // Blocks added to the per-account index per backfill timer
 
 // Helper functions
 fn get_caller_account() -> Account {
@@ -60,6 +249,12 @@ fn get_caller_account() -> Account {
         owner: ic_cdk::caller(),
         subaccount: DEFAULT_SUBACCOUNT,
     }
+    .canonical()
+}
+
+// Helper function to check whether the caller controls this canister
+fn caller_is_controller() -> bool {
+    ic_cdk::api::is_controller(&ic_cdk::caller())
 }
 
 // Helper function to get account balance
@@ -73,16 +268,124 @@ fn get_account_balance(account: &Account) -> Nat {
     })
 }
 
+// Helper function to check whether crediting `account` would exceed the subaccount limit
+// Only accounts without a balance yet count as new subaccounts
+fn exceeds_subaccount_limit(account: &Account) -> bool {
+    let limit_set = TOKEN_DATA.with(|data| data.borrow().max_subaccounts_per_principal.is_some());
+    if !limit_set || BALANCES.with(|balances| balances.borrow().contains_key(account)) {
+        return false;
+    }
+
+    let first = Account {
+        owner: account.owner,
+        subaccount: None,
+    };
+    let existing = BALANCES.with(|balances| {
+        balances
+            .borrow()
+            .range(first..)
+            .take_while(|(funded, _)| funded.owner == account.owner)
+            .count() as u32
+    });
+
+    !TOKEN_DATA.with(|data| data.borrow().allows_new_subaccount(existing))
+}
+
+// Helper function to check whether `amount` exceeds the transfer limit of the sender's KYC tier
+fn exceeds_kyc_limit(account: &Account, amount: &Nat) -> bool {
+    let tier = KYC_TIERS.with(|tiers| tiers.borrow().get(account).unwrap_or(0));
+    TOKEN_DATA.with(|data| data.borrow().kyc_limit(tier))
+        .map(|limit| *amount > limit)
+        .unwrap_or(false)
+}
+
+// Helper function to check whether an account's reputation is below the configured minimum
+fn below_min_reputation(account: &Account) -> bool {
+    let score = REPUTATIONS.with(|reputations| reputations.borrow().get(account).unwrap_or(Reputation(0)));
+    TOKEN_DATA.with(|data| data.borrow().below_min_reputation(score))
+}
+
+// Helper function to add to the running total of fees paid by an account
+fn add_fees_paid(account: &Account, fee: &Nat) {
+    FEES_PAID.with(|fees| {
+        let mut fees = fees.borrow_mut();
+        let paid = fees.get(account).unwrap_or_else(|| StableNat::from(0u64));
+        fees.insert(account.clone(), paid + StableNat::from_nat(fee.clone()));
+    });
+}
+
+// Helper function to find the first block whose timestamp is at least `ts`, by binary search since block
+// timestamps never decrease; returns one past the newest block if there is none
+fn first_block_since(ts: u64) -> u64 {
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let (start, end) = match (txs.first_key_value(), txs.last_key_value()) {
+            (Some((first, _)), Some((last, _))) => (first.0, last.0 + 1),
+            _ => return 0,
+        };
+        let timestamp_at = |index: u64| txs.get(&StableBlockIndex(index)).map(|tx| tx.timestamp).unwrap_or(u64::MAX);
+        first_index_where(start, end, |index| timestamp_at(index) >= ts)
+    })
+}
+
+// Helper function to credit a collected fee to the fee collector, or burn it from the total supply if none is set
+// Returns the collector to record in the fee's block (None for a burned or zero fee)
+fn collect_fee(fee: &Nat) -> Option<Account> {
+    if fee.0.is_zero() {
+        return None;
+    }
+
+    let fee_collector = TOKEN_DATA.with(|data| data.borrow().fee_collector.clone());
+    match &fee_collector {
+        Some(collector) => BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            let balance = balances.get(collector).unwrap_or_else(|| StableNat::from(0u64));
+            balances.insert(collector.clone(), balance + StableNat::from_nat(fee.clone()));
+        }),
+        None => TOKEN_DATA.with(|data| {
+            // The fee came out of a balance, so it cannot exceed a consistent supply; never trap if it does
+            let _ = data.borrow_mut().reduce_supply(fee);
+        }),
+    }
+    fee_collector
+}
+
 fn record_transaction(tx: Transaction) -> BlockIndex {
+    // Close the previous day with a summary block ahead of the new day's first block, if enabled
+    let finished_day = TOKEN_DATA.with(|data| data.borrow_mut().track_daily_totals(&tx));
+    if let Some(summary) = finished_day {
+        append_block(Transaction::daily_summary(summary, tx.timestamp));
+    }
+    append_block(tx)
+}
+
+// Helper function to chain a block to the log and index it
+fn append_block(mut tx: Transaction) -> BlockIndex {
+    // Chain the block to its predecessor; its own hash becomes the next block's phash
+    tx.phash = TOKEN_DATA.with(|data| data.borrow().last_block_hash.clone());
+    let block_hash = hash_value(&transaction_to_value(&tx));
+    
     let block_index = TOKEN_DATA.with(|data| {
         let mut data = data.borrow_mut();
         let current_index = data.next_block_index.clone();
         data.next_block_index += 1u64;
+        data.last_block_hash = Some(block_hash.to_vec());
+        data.track_largest_transaction(&current_index, &tx);
         current_index
     });
+    certify_tip();
 
     let stable_block_index = StableBlockIndex::from_nat(&block_index);
     
+    LAST_ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        for account in tx.accounts() {
+            activity.insert(account, tx.timestamp);
+        }
+    });
+
+    ACCOUNT_BLOCKS.with(|index| index_account_blocks(&mut index.borrow_mut(), &tx, stable_block_index.0));
+    
     TRANSACTIONS.with(|txs| {
         txs.borrow_mut().insert(stable_block_index, tx);
     });
@@ -90,6 +393,273 @@ fn record_transaction(tx: Transaction) -> BlockIndex {
     block_index
 }
 
+// Helper function to build the key a delegation from `owner` to `delegate` is stored under
+fn delegation_key(owner: &Account, delegate: Principal) -> AccountPair {
+    AccountPair(owner.clone(), Account {
+        owner: delegate,
+        subaccount: None,
+    })
+}
+
+// Helper function to check that `delegate` may spend `total` from `owner` and return the reduced cap
+fn check_delegation(owner: &Account, delegate: Principal, total: &Nat) -> Result<Allowance, TransferError> {
+    if !TOKEN_DATA.with(|data| data.borrow().delegations_enabled) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::Unauthorized.to_nat(),
+            message: "Delegated transfers are disabled".to_string(),
+        });
+    }
+
+    let delegation = DELEGATIONS.with(|delegations| delegations.borrow().get(&delegation_key(owner, delegate)));
+    match delegation {
+        Some(delegation) if delegation.covers(total, time()) => Ok(Allowance {
+            allowance: delegation.allowance - total.clone(),
+            expires_at: delegation.expires_at,
+        }),
+        Some(_) => Err(TransferError::GenericError {
+            error_code: ErrorCode::DelegationExceeded.to_nat(),
+            message: "Transfer exceeds the delegation's remaining cap or the delegation has expired".to_string(),
+        }),
+        None => Err(TransferError::GenericError {
+            error_code: ErrorCode::Unauthorized.to_nat(),
+            message: "Caller holds no delegation for this account".to_string(),
+        }),
+    }
+}
+
+// Helper function to sweep an opted-in account's dust balance into the consolidation account
+// The sweep is recorded as a fee-free transfer tagged with the "dust" category
+fn consolidate_dust(account: &Account) {
+    if !DUST_OPT_INS.with(|opt_ins| opt_ins.borrow().contains_key(account)) {
+        return;
+    }
+
+    let balance = get_account_balance(account);
+    let target = match TOKEN_DATA.with(|data| data.borrow().dust_consolidation_target(account, &balance)) {
+        Some(target) => target,
+        None => return,
+    };
+
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        balances.remove(account);
+        let target_balance = balances.get(&target).unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(target.clone(), target_balance + StableNat::from_nat(balance.clone()));
+    });
+
+    let transfer = Transfer {
+        amount: balance,
+        from: account.clone(),
+        to: target,
+        spender: None,
+        memo: None,
+        fee: None,
+        created_at_time: None,
+        category: Some("dust".to_string()),
+    };
+    record_transaction(Transaction::transfer(transfer, time()));
+}
+
+// Canister initialization
+// Name, symbol, decimals, fee and minting account default to the values above unless supplied
+// With emit_genesis_block set, block 0 describes the ledger and transactions start at block 1
+#[init]
+fn init(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    TOKEN_DATA.with(|data| data.borrow_mut().apply_init_args(&args));
+
+    if args.emit_genesis_block.unwrap_or(false) {
+        let genesis = TOKEN_DATA.with(|data| data.borrow().genesis());
+        record_transaction(Transaction::genesis(genesis, time()));
+    }
+    start_fee_oracle_timer();
+    start_module_hash_fetch(Duration::ZERO);
+}
+
+// Helper function to poll the fee oracle every FEE_ORACLE_INTERVAL, since timers do not survive upgrades
+fn start_fee_oracle_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(FEE_ORACLE_INTERVAL), || ic_cdk::spawn(poll_fee_oracle()));
+}
+
+// Save the token data to stable memory; balances, allowances and blocks already live there
+#[pre_upgrade]
+fn pre_upgrade() {
+    let data = TOKEN_DATA.with(|data| data.borrow().clone());
+    TOKEN_DATA_CELL.with(|cell| {
+        cell.borrow_mut().set(data).expect("failed to save token data");
+    });
+}
+
+// Restore the token data saved by pre_upgrade
+#[post_upgrade]
+fn post_upgrade() {
+    let data = TOKEN_DATA_CELL.with(|cell| cell.borrow().get().clone());
+    TOKEN_DATA.with(|token_data| *token_data.borrow_mut() = data);
+    merge_default_subaccounts();
+    certify_tip();
+    schedule_pending_transfers();
+    schedule_pending_finalizations();
+    start_fee_oracle_timer();
+    start_module_hash_fetch(Duration::ZERO);
+    if !TOKEN_DATA.with(|data| data.borrow().account_blocks_backfilled.unwrap_or(false)) {
+        ic_cdk_timers::set_timer(Duration::ZERO, || backfill_account_blocks(0));
+    }
+}
+
+// Helper function to add blocks recorded before the per-account index existed to it, BACKFILL_CHUNK_SIZE
+// blocks per timer starting at `start`; indexing is idempotent, so an upgrade mid-way simply starts over
+fn backfill_account_blocks(start: u64) {
+    let end = start.saturating_add(BACKFILL_CHUNK_SIZE);
+    let chunk: Vec<(StableBlockIndex, Transaction)> = TRANSACTIONS.with(|txs| {
+        txs.borrow().range(StableBlockIndex::new(start)..StableBlockIndex::new(end)).collect()
+    });
+    ACCOUNT_BLOCKS.with(|index| {
+        let mut index = index.borrow_mut();
+        for (block, tx) in &chunk {
+            index_account_blocks(&mut index, tx, block.0);
+        }
+    });
+
+    if chunk.len() as u64 == BACKFILL_CHUNK_SIZE {
+        ic_cdk_timers::set_timer(Duration::ZERO, move || backfill_account_blocks(end));
+    } else {
+        TOKEN_DATA.with(|data| data.borrow_mut().account_blocks_backfilled = Some(true));
+    }
+}
+
+// Helper function to merge entries stored under the all-zero subaccount into the default account, once per ledger
+// Ledgers before canonicalization keyed the two forms separately; balances, fees paid and fee debts are summed,
+// activity keeps the latest timestamp, and other maps keep the default account's entry when both exist
+fn merge_default_subaccounts() {
+    if TOKEN_DATA.with(|data| data.borrow().default_subaccounts_merged.unwrap_or(false)) {
+        return;
+    }
+
+    let account = |account: &Account| account.clone().canonical();
+    let pair = |pair: &AccountPair| AccountPair(pair.0.clone().canonical(), pair.1.clone().canonical());
+    fn keep_existing<V>(existing: V, _moved: V) -> V {
+        existing
+    }
+
+    BALANCES.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, |a, b| a + b));
+    FEES_PAID.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, |a, b| a + b));
+    FEE_DEBTS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, |a, b| a + b));
+    LAST_ACTIVITY.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, u64::max));
+    ACCOUNT_LABELS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, keep_existing));
+    DUST_OPT_INS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, keep_existing));
+    KYC_TIERS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, keep_existing));
+    REPUTATIONS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), account, keep_existing));
+    ALLOWANCES.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), pair, keep_existing));
+    APPROVED_AMOUNTS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), pair, keep_existing));
+    LAST_APPROVE_BLOCKS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), pair, keep_existing));
+    DELEGATIONS.with(|map| merge_into_canonical_keys(&mut map.borrow_mut(), pair, keep_existing));
+    ACCOUNT_BLOCKS.with(|map| {
+        merge_into_canonical_keys(&mut map.borrow_mut(), |key| AccountBlock(key.0.clone().canonical(), key.1.clone()), keep_existing)
+    });
+
+    // Accounts held in pending entries are credited later, so they are rewritten too
+    SCHEDULED_TRANSFERS.with(|map| {
+        canonicalize_values(&mut map.borrow_mut(), |scheduled| ScheduledTransfer {
+            from: scheduled.from.clone().canonical(),
+            to: scheduled.to.clone().canonical(),
+            ..scheduled.clone()
+        })
+    });
+    PENDING_TRANSFERS.with(|map| {
+        canonicalize_values(&mut map.borrow_mut(), |pending| PendingTransfer {
+            from: pending.from.clone().canonical(),
+            to: pending.to.clone().canonical(),
+            ..pending.clone()
+        })
+    });
+    ESCROWS.with(|map| {
+        canonicalize_values(&mut map.borrow_mut(), |escrow| Escrow {
+            from: escrow.from.clone().canonical(),
+            to: escrow.to.clone().canonical(),
+            ..escrow.clone()
+        })
+    });
+    PAYMENT_REQUESTS.with(|map| {
+        canonicalize_values(&mut map.borrow_mut(), |request| PaymentRequest {
+            merchant: request.merchant.clone().canonical(),
+            ..request.clone()
+        })
+    });
+    ALIASES.with(|map| canonicalize_values(&mut map.borrow_mut(), account));
+
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.canonicalize_accounts();
+        data.default_subaccounts_merged = Some(true);
+    });
+}
+
+// Helper function to set a timer again for each pending scheduled transfer, since timers do not survive upgrades
+fn schedule_pending_transfers() {
+    let now = time();
+    let mut execute_times: Vec<u64> = SCHEDULED_TRANSFERS.with(|scheduled_transfers| {
+        scheduled_transfers.borrow().iter().map(|((execute_at, _), _)| execute_at).collect()
+    });
+    execute_times.dedup();
+    for execute_at in execute_times {
+        ic_cdk_timers::set_timer(Duration::from_nanos(execute_at.saturating_sub(now)), execute_due_transfers);
+    }
+}
+
+// Helper function to set a timer again for each transfer still held for its dispute window
+fn schedule_pending_finalizations() {
+    let now = time();
+    let mut final_times: Vec<u64> = PENDING_TRANSFERS.with(|pending_transfers| {
+        pending_transfers.borrow().iter().map(|(_, pending)| pending.final_at).collect()
+    });
+    final_times.sort_unstable();
+    final_times.dedup();
+    for final_at in final_times {
+        ic_cdk_timers::set_timer(Duration::from_nanos(final_at.saturating_sub(now)), finalize_due_transfers);
+    }
+}
+
+// Helper function to set the certified data to the root of the tip tree (last block index and hash)
+// Certified data does not survive upgrades, so post_upgrade sets it again
+fn certify_tip() {
+    let tip = TOKEN_DATA.with(|data| data.borrow().tip());
+    if let Some((last_block_index, last_block_hash)) = tip {
+        ic_cdk::api::set_certified_data(&tip_tree_root(last_block_index, &last_block_hash));
+    }
+}
+
+// Helper function to look up the block of an identical transaction still inside the window
+fn find_duplicate(key: &DedupKey) -> Option<Nat> {
+    RECENT_TRANSACTIONS.with(|recent| recent.borrow().get(key)).map(|block_index| block_index.to_nat())
+}
+
+// Helper function to index a recorded transaction for deduplication (only if it carried a created_at_time)
+fn remember_transaction(key: Option<DedupKey>, block_index: &Nat) {
+    if let Some(key) = key {
+        RECENT_TRANSACTIONS.with(|recent| {
+            recent.borrow_mut().insert(key, StableBlockIndex::from_nat(block_index));
+        });
+    }
+}
+
+// Helper function to drop deduplication entries that have left the transaction window
+// Removes at most MAX_BATCH_SIZE entries per call to bound the cost of a single update
+fn prune_recent_transactions() {
+    let now = time();
+    RECENT_TRANSACTIONS.with(|recent| {
+        let mut recent = recent.borrow_mut();
+        let expired: Vec<DedupKey> = recent
+            .iter()
+            .map(|(key, _)| key)
+            .take_while(|key| key.is_expired(now, TX_WINDOW))
+            .take(MAX_BATCH_SIZE)
+            .collect();
+        for key in expired {
+            recent.remove(&key);
+        }
+    });
+}
+
 // ICRC-1 Standard Query Methods
 #[query]
 fn icrc1_name() -> String {
@@ -113,12 +683,44 @@ fn icrc1_fee() -> Nat {
 
 #[query]
 fn icrc1_metadata() -> Vec<(String, Value)> {
-    vec![
+    let mut metadata = vec![
         ("icrc1:name".to_string(), Value::Text(icrc1_name())),
         ("icrc1:symbol".to_string(), Value::Text(icrc1_symbol())),
         ("icrc1:decimals".to_string(), Value::Nat(Nat::from(icrc1_decimals() as u64))),
         ("icrc1:fee".to_string(), Value::Nat(icrc1_fee())),
-    ]
+    ];
+
+    if let Some(limit) = max_subaccounts_per_principal() {
+        metadata.push((
+            "icrc1:max_subaccounts_per_principal".to_string(),
+            Value::Nat(Nat::from(limit)),
+        ));
+    }
+
+    if let Some(expiry) = default_approval_expiry() {
+        metadata.push((
+            "icrc1:default_approval_expiry".to_string(),
+            Value::Nat(Nat::from(expiry)),
+        ));
+    }
+
+    metadata.push((
+        "icrc1:min_burn_amount".to_string(),
+        Value::Nat(TOKEN_DATA.with(|data| data.borrow().min_burn_amount())),
+    ));
+
+    metadata
+}
+
+#[query]
+fn max_subaccounts_per_principal() -> Option<u32> {
+    TOKEN_DATA.with(|data| data.borrow().max_subaccounts_per_principal)
+}
+
+// Lifetime in nanoseconds applied to approvals that omit expires_at, if configured
+#[query]
+fn default_approval_expiry() -> Option<u64> {
+    TOKEN_DATA.with(|data| data.borrow().default_approval_expiry)
 }
 
 #[query]
@@ -133,463 +735,3051 @@ fn icrc1_minting_account() -> Option<Account> {
 
 #[query]
 fn icrc1_balance_of(account: Account) -> Nat {
+    let account = account.canonical();
     get_account_balance(&account)
 }
 
-// ICRC-1 Transfer
+// Balances of several accounts in input order, looked up the same way as icrc1_balance_of
+#[query]
+fn icrc1_balance_of_batch(accounts: Vec<Account>) -> Vec<Nat> {
+    accounts.into_iter().map(|account| get_account_balance(&account.canonical())).collect()
+}
+
+#[query]
+fn get_account_label(account: Account) -> Option<String> {
+    let account = account.canonical();
+    ACCOUNT_LABELS.with(|labels| labels.borrow().get(&account).map(|label| label.0))
+}
+
+// Set or clear a human-readable label on an account (only callable by the account owner)
+// Labels are metadata only and never affect balances
 #[update]
-fn icrc1_transfer(args: TransferArgs) -> TransferResult {
-    let caller = ic_cdk::caller();
-    let from = Account {
-        owner: caller,
-        subaccount: args.from_subaccount,
-    };
-    let to = args.to;
-    let amount = args.amount.clone();
-    let fee = args.fee.unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().fee.clone()));
-    let memo = args.memo;
-    let created_at_time = args.created_at_time;
-    
-    // Validate the transaction
-    if let Some(created_at) = created_at_time {
-        let now = time();
-        if created_at > now {
-            return TransferResult::Err(TransferError::CreatedInFuture { ledger_time: now });
+fn set_account_label(account: Account, label: Option<String>) -> Result<(), String> {
+    let account = account.canonical();
+    // Check if the caller owns the account
+    AccountLabel::check_owner(&account, ic_cdk::caller())?;
+
+    match label {
+        Some(label) => {
+            let label = AccountLabel::new(label)?;
+            ACCOUNT_LABELS.with(|labels| {
+                labels.borrow_mut().insert(account, label);
+            });
         }
-        if now > created_at + TX_WINDOW {
-            return TransferResult::Err(TransferError::TooOld);
+        None => {
+            ACCOUNT_LABELS.with(|labels| {
+                labels.borrow_mut().remove(&account);
+            });
         }
     }
-    
-    // Check if the fee is correct
-    let expected_fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
-    if fee != expected_fee {
-        return TransferResult::Err(TransferError::BadFee { expected_fee });
-    }
-    
-    // Check if the sender has enough funds
-    let from_balance = get_account_balance(&from);
-    let total_deduction = amount.clone() + fee.clone();
-    if from_balance < total_deduction {
-        return TransferResult::Err(TransferError::InsufficientFunds { balance: from_balance });
-    }
-    
-    // Update balances
-    BALANCES.with(|balances| {
-        let mut balances = balances.borrow_mut();
-        
-        // Convert Nat to StableNat for storage
-        let stable_amount = StableNat::from_nat(amount.clone());
-        let total_deduction_clone = total_deduction.clone();
-        let from_balance_clone = from_balance.clone();
-        
-        // Deduct from sender
-        if from_balance_clone == total_deduction_clone {
-            // If exact amount, remove the entry
-            balances.remove(&from);
-        } else {
-            // Otherwise, update with new balance
-            let new_stable_balance = StableNat::from_nat(from_balance_clone - total_deduction_clone);
-            balances.insert(from.clone(), new_stable_balance);
+
+    Ok(())
+}
+
+// Whether an error code (as returned in GenericError) is worth retrying
+#[query]
+fn is_retriable_error_code(code: Nat) -> bool {
+    code.0
+        .to_u64()
+        .and_then(ErrorCode::from_code)
+        .map(|code| code.is_retriable())
+        .unwrap_or(false)
+}
+
+// Total fees a batch of `count` transfers will cost, so clients can pre-fund it
+#[query]
+fn estimate_batch_fee(count: u32) -> Nat {
+    TOKEN_DATA.with(|data| data.borrow().batch_fee(count))
+}
+
+// Number of accounts per balance bucket, where `buckets` are the sorted bucket boundaries
+// Returns buckets.len() + 1 counts: below the first boundary, between each pair, and above the last
+#[query]
+fn balance_histogram(buckets: Vec<Nat>) -> Vec<u64> {
+    let mut boundaries = buckets;
+    boundaries.sort();
+
+    let mut counts = vec![0u64; boundaries.len() + 1];
+    BALANCES.with(|balances| {
+        for (_, balance) in balances.borrow().iter() {
+            counts[histogram_bucket(&boundaries, balance.as_nat())] += 1;
         }
-        
-        // Add to recipient
-        let stable_to_balance = balances.get(&to)
-            .unwrap_or_else(|| StableNat::from(0u64));
-        balances.insert(to.clone(), stable_to_balance + stable_amount);
     });
-    
-    // Record the transaction
-    let transfer = Transfer {
-        amount: amount.clone(),
-        from: from.clone(),
-        to: to.clone(),
-        spender: None,
-        memo: memo.clone(),
-        fee: Some(fee.clone()),
-        created_at_time,
+
+    counts
+}
+
+// Helper function to read the blocks touching `account` from block `start` onward via the per-account index
+// At most MAX_SCAN_BLOCKS of the account's blocks are read
+fn indexed_transactions(account: &Account, start: u64) -> Vec<Transaction> {
+    let block_indices = ACCOUNT_BLOCKS.with(|index| account_blocks(&index.borrow(), account, start, MAX_SCAN_BLOCKS as usize));
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        block_indices
+            .into_iter()
+            .filter_map(|index| txs.get(&StableBlockIndex::new(index)))
+            .collect()
+    })
+}
+
+// Diagnostic: compare an account's stored balance with the balance obtained by replaying the log
+// Returns (stored balance, replayed balance, whether they match)
+// Replays the account's blocks from the per-account index; an account in more than MAX_SCAN_BLOCKS blocks
+// is replayed only up to that many and will not match
+#[query]
+fn verify_account_balance(account: Account) -> (Nat, Nat, bool) {
+    let account = account.canonical();
+    let stored = get_account_balance(&account);
+
+    let (credits, debits) = indexed_transactions(&account, 0).iter().fold(
+        (Nat::from(0u64), Nat::from(0u64)),
+        |(credits, debits), tx| {
+            let (credit, debit) = tx.balance_change(&account);
+            (credits + credit, debits + debit)
+        },
+    );
+
+    // A log that debits more than it credits is itself inconsistent; report it as zero
+    let replayed = if credits >= debits {
+        credits - debits
+    } else {
+        Nat::from(0u64)
     };
-    
-    let tx = Transaction::transfer(transfer, time());
-    let block_index = record_transaction(tx);
-    
-    TransferResult::Ok(block_index)
+
+    let matches = stored == replayed;
+    (stored, replayed, matches)
 }
 
-// ICRC-2 Approve
+// Signed change in an account's balance from `since_block` to the tip, found by replaying the log
+// Only the account's own blocks are replayed, via the per-account index, and at most MAX_SCAN_BLOCKS of them
+#[query]
+fn balance_delta(account: Account, since_block: Nat) -> Int {
+    let account = account.canonical();
+    let since = since_block.0.to_u64().unwrap_or(u64::MAX);
+
+    indexed_transactions(&account, since)
+        .iter()
+        .fold(Int::from(0), |delta, tx| delta + tx.balance_delta(&account))
+}
+
+// (total minted, total burned) by the blocks in [start, start + length), for charting net supply change
+// At most MAX_SCAN_BLOCKS blocks are scanned
+#[query]
+fn mint_burn_summary(start: Nat, length: Nat) -> (Nat, Nat) {
+    let start = start.0.to_u64().unwrap_or(u64::MAX);
+    let length = std::cmp::min(length.0.to_u64().unwrap_or(u64::MAX), MAX_SCAN_BLOCKS);
+    let end = start.saturating_add(length);
+
+    TRANSACTIONS.with(|txs| {
+        txs.borrow()
+            .range(StableBlockIndex::new(start)..StableBlockIndex::new(end))
+            .fold((Nat::from(0u64), Nat::from(0u64)), |(minted, burned), (_, tx)| {
+                let (tx_minted, tx_burned) = tx.supply_change();
+                (minted + tx_minted, burned + tx_burned)
+            })
+    })
+}
+
+// (day timestamp, minted, burned) for each of `days` days starting at `day_start_ns`, for supply charts
+// At most MAX_SCAN_BLOCKS blocks from the start of the window are scanned and `days` is capped at MAX_PAGE_SIZE
+#[query]
+fn supply_timeseries(day_start_ns: u64, days: u32) -> Vec<(u64, Nat, Nat)> {
+    let days = std::cmp::min(days, MAX_PAGE_SIZE);
+    let window_end = day_start_ns.saturating_add((days as u64).saturating_mul(NANOS_PER_DAY));
+
+    let first = first_block_since(day_start_ns);
+
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let in_window = txs
+            .range(StableBlockIndex(first)..)
+            .take(MAX_SCAN_BLOCKS as usize)
+            .map(|(_, tx)| tx)
+            .take_while(|tx| tx.timestamp < window_end);
+        supply_by_day(in_window, day_start_ns, days)
+    })
+}
+
+// Total fees an account has paid for transfers and approvals
+#[query]
+fn total_fees_paid_by(account: Account) -> Nat {
+    let account = account.canonical();
+    FEES_PAID.with(|fees| {
+        fees.borrow()
+            .get(&account)
+            .map(|paid| paid.into_nat())
+            .unwrap_or_else(|| Nat::from(0u64))
+    })
+}
+
+// (sum of the `n` largest balances, total supply) for computing the concentration ratio
+// Iterates every funded account
+#[query]
+fn supply_concentration(n: u32) -> (Nat, Nat) {
+    let top = BALANCES.with(|balances| {
+        top_balances_sum(balances.borrow().iter().map(|(_, balance)| balance.into_nat()), n as usize)
+    });
+    let total_supply = TOKEN_DATA.with(|data| data.borrow().total_supply.clone());
+
+    (top, total_supply)
+}
+
+// The `n` funded accounts that have been inactive the longest, with their last activity timestamp
+// Iterates every funded account; `n` is capped at MAX_PAGE_SIZE
+#[query]
+fn most_dormant_accounts(n: u32) -> Vec<(Account, u64)> {
+    let funded: Vec<(Account, u64)> = BALANCES.with(|balances| {
+        balances
+            .borrow()
+            .iter()
+            .filter(|(_, balance)| !balance.as_nat().0.is_zero())
+            .map(|(account, _)| {
+                let last_activity = LAST_ACTIVITY.with(|activity| activity.borrow().get(&account).unwrap_or(0));
+                (account, last_activity)
+            })
+            .collect()
+    });
+
+    most_dormant(funded, std::cmp::min(n, MAX_PAGE_SIZE) as usize)
+}
+
+// Received, sent, fees paid and net change of an account over blocks with timestamps in [from_ts, to_ts]
+// Served from the per-account index; at most MAX_SCAN_BLOCKS of the account's blocks are read
+#[query]
+fn account_statement(account: Account, from_ts: u64, to_ts: u64) -> Statement {
+    let account = account.canonical();
+    let first = first_block_since(from_ts);
+    let block_indices = ACCOUNT_BLOCKS.with(|index| {
+        account_blocks(&index.borrow(), &account, first, MAX_SCAN_BLOCKS as usize)
+    });
+
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let in_window = block_indices
+            .into_iter()
+            .filter_map(|index| txs.get(&StableBlockIndex::new(index)))
+            .take_while(|tx| tx.timestamp <= to_ts);
+        Statement::for_account(&account, in_window)
+    })
+}
+
+// (first, last) block indices an account appears in, or None if it has no activity
+#[query]
+fn account_activity_bounds(account: Account) -> Option<(Nat, Nat)> {
+    let account = account.canonical();
+    ACCOUNT_BLOCKS
+        .with(|index| account_block_bounds(&index.borrow(), &account))
+        .map(|(first, last)| (Nat::from(first), Nat::from(last)))
+}
+
+// Full configuration snapshot for operators and explorers
+#[query]
+fn get_config() -> TokenConfig {
+    TOKEN_DATA.with(|data| data.borrow().config())
+}
+
+// Helper function to apply the sender-side rules of icrc1_transfer to any path that moves funds out of `from`
+// Covers the icrc1_transfer switch, zero amounts, the sender's KYC tier limit and the reputation minimum
+fn check_sender(from: &Account, amount: &Nat) -> Result<(), TransferError> {
+    if method_disabled("icrc1_transfer") {
+        return Err(TransferError::TemporarilyUnavailable);
+    }
+
+    // Reject zero-amount transfers
+    if let Err(message) = validate_amount(amount) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidAmount.to_nat(),
+            message,
+        });
+    }
+
+    // Enforce the transfer limit of the sender's KYC tier
+    if exceeds_kyc_limit(from, amount) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::KycLimitExceeded.to_nat(),
+            message: "Amount exceeds the transfer limit of the sender's KYC tier".to_string(),
+        });
+    }
+
+    // Block senders whose reputation is below the minimum
+    if below_min_reputation(from) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::ReputationTooLow.to_nat(),
+            message: "Sender's reputation is below the minimum required to transfer".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// ICRC-1 Transfer
 #[update]
-fn icrc2_approve(args: ApproveArgs) -> ApproveResult {
+fn icrc1_transfer(args: TransferArgs) -> TransferResult {
+    // A delegate transfers as the owner named in on_behalf_of
     let caller = ic_cdk::caller();
+    let delegator = args.on_behalf_of;
     let from = Account {
-        owner: caller,
-        subaccount: args.from_subaccount,
-    };
-    let spender = args.spender;
+        owner: delegator.unwrap_or(caller),
+        subaccount: args.from_subaccount.clone(),
+    }
+    .canonical();
+    let to = args.to.clone().canonical();
     let amount = args.amount.clone();
-    let expected_allowance = args.expected_allowance.clone();
-    let expires_at = args.expires_at;
+
+    check_sender(&from, &amount)?;
+
+    // Reject transfers that arrive after the client's deadline
+    if args.is_past_deadline(time()) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::DeadlinePassed.to_nat(),
+            message: "Transfer deadline has passed".to_string(),
+        });
+    }
+
+    let fee_implicit = args.fee.is_none();
     let fee = args.fee.unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().fee.clone()));
     let memo = args.memo;
     let created_at_time = args.created_at_time;
+    let category = args.category;
+    let tip = args.tip.filter(|tip| !tip.0.is_zero());
+    let tip_amount = tip.clone().unwrap_or_else(|| Nat::from(0u64));
+    
+    // Validate the category tag
+    if let Some(category) = &category {
+        if let Err(message) = validate_category(category) {
+            return TransferResult::Err(TransferError::GenericError {
+                error_code: ErrorCode::InvalidCategory.to_nat(),
+                message,
+            });
+        }
+    }
+    
+    // Validate the memo length
+    if let Err(message) = validate_memo(memo.as_deref()) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+    
+    // The minimum burn amount also applies to transfers into the minting account
+    if let Err(min_burn_amount) = TOKEN_DATA.with(|data| data.borrow().check_min_burn_to(&to, &amount)) {
+        return TransferResult::Err(TransferError::BadBurn { min_burn_amount });
+    }
     
     // Validate the transaction
     if let Some(created_at) = created_at_time {
         let now = time();
         if created_at > now {
-            return ApproveResult::Err(ApproveError::CreatedInFuture { ledger_time: now });
+            return TransferResult::Err(TransferError::CreatedInFuture { ledger_time: now });
         }
         if now > created_at + TX_WINDOW {
-            return ApproveResult::Err(ApproveError::TooOld);
+            return TransferResult::Err(TransferError::TooOld);
         }
     }
     
+    // Reject a retry of a transfer already recorded within the window
+    prune_recent_transactions();
+    let dedup_key = created_at_time.map(|created_at_time| DedupKey {
+        created_at_time,
+        kind: "transfer".to_string(),
+        caller,
+        from: from.clone(),
+        to: to.clone(),
+        amount: amount.clone(),
+        fee: fee.clone(),
+        memo: memo.clone(),
+    });
+    if let Some(duplicate_of) = dedup_key.as_ref().and_then(find_duplicate) {
+        return TransferResult::Err(TransferError::Duplicate { duplicate_of });
+    }
+    
     // Check if the fee is correct
     let expected_fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
     if fee != expected_fee {
-        return ApproveResult::Err(ApproveError::BadFee { expected_fee });
+        return TransferResult::Err(TransferError::BadFee { expected_fee });
+    }
+
+    // Reject transfers where the fee would eat the whole amount, if enabled
+    if TOKEN_DATA.with(|data| data.borrow().fee_exceeds_amount(&amount)) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::FeeExceedsAmount.to_nat(),
+            message: "Transfer amount must exceed the fee".to_string(),
+        });
     }
+
+    // Fees accrue as debt instead of being charged per transfer, if enabled
+    let fee_debt = TOKEN_DATA.with(|data| data.borrow().accrue_fee_debt(&get_fee_debt(&from), &fee))?;
+    let charged_fee = if fee_debt.is_some() { Nat::from(0u64) } else { fee.clone() };
     
-    // Check if the sender has enough funds for the fee
+    // Check if the sender has enough funds, keeping enough back to settle any fee debt
     let from_balance = get_account_balance(&from);
-    if from_balance < fee {
-        return ApproveResult::Err(ApproveError::InsufficientFunds { balance: from_balance });
+    let total_deduction = amount.clone() + charged_fee.clone() + tip_amount.clone();
+    if from_balance < total_deduction.clone() + fee_debt.clone().unwrap_or_else(|| Nat::from(0u64)) {
+        return TransferResult::Err(TransferError::InsufficientFunds { balance: from_balance });
     }
     
-    // Check if the current allowance matches the expected allowance
-    if let Some(expected) = &expected_allowance {
-        let current = ALLOWANCES.with(|allowances| {
-            allowances
-                .borrow()
-                .get(&AccountPair(from.clone(), spender.clone()))
-                .map(|a| a.allowance.clone())
-                .unwrap_or_else(|| Nat::from(0u64))
+    // Check the caller's delegation when spending from another principal's account
+    let remaining_delegation = match delegator {
+        Some(_) => match check_delegation(&from, caller, &(amount.clone() + fee.clone() + tip_amount.clone())) {
+            Ok(remaining) => Some(remaining),
+            Err(err) => return TransferResult::Err(err),
+        },
+        None => None,
+    };
+    
+    // Check if the recipient may open another subaccount
+    if exceeds_subaccount_limit(&to) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::SubaccountLimitReached.to_nat(),
+            message: "Recipient has reached the maximum number of subaccounts".to_string(),
         });
-        
-        if &current != expected {
-            return ApproveResult::Err(ApproveError::AllowanceChanged { current_allowance: current });
-        }
     }
     
-    // Check if the approval has expired
-    if let Some(expires) = expires_at {
-        let now = time();
-        if expires < now {
-            return ApproveResult::Err(ApproveError::Expired { ledger_time: now });
-        }
+    // Transfers may be held in the dispute account for the dispute window before reaching the recipient
+    let dispute_window = TOKEN_DATA.with(|data| data.borrow().dispute_window);
+    let credited = if dispute_window.is_some() { dispute_account() } else { to.clone() };
+    
+    // The tip goes to the tip account, or is burned if none is set
+    let tip = tip.map(|amount| Tip {
+        amount,
+        to: TOKEN_DATA.with(|data| data.borrow().tip_account.clone()),
+    });
+    
+    // Burn the tip before touching balances, so an inconsistent supply fails the transfer instead of trapping
+    if let Some(Tip { amount, to: None }) = &tip {
+        TOKEN_DATA.with(|data| data.borrow_mut().reduce_supply(amount))?;
     }
     
-    // Update balances for the fee
+    // Update balances
     BALANCES.with(|balances| {
         let mut balances = balances.borrow_mut();
         
-        // Convert fee to StableNat
-        let fee_clone = fee.clone();
+        // Convert Nat to StableNat for storage
+        let stable_amount = StableNat::from_nat(amount.clone());
+        let total_deduction_clone = total_deduction.clone();
         let from_balance_clone = from_balance.clone();
         
-        if from_balance_clone == fee_clone {
-            // If exact fee amount, remove the entry
+        // Deduct from sender
+        if from_balance_clone == total_deduction_clone {
+            // If exact amount, remove the entry
             balances.remove(&from);
         } else {
             // Otherwise, update with new balance
-            let new_stable_balance = StableNat::from_nat(from_balance_clone - fee_clone);
+            let new_stable_balance = StableNat::from_nat(from_balance_clone - total_deduction_clone);
             balances.insert(from.clone(), new_stable_balance);
         }
-    });
-    
-    // Update allowance
-    let allowance = Allowance {
-        allowance: amount.clone(),
-        expires_at,
-    };
-    
-    ALLOWANCES.with(|allowances| {
-        allowances.borrow_mut().insert(AccountPair(from.clone(), spender.clone()), allowance);
+        
+        // Add to recipient, or to the dispute account while the transfer is held
+        let stable_to_balance = balances.get(&credited)
+            .unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(credited.clone(), stable_to_balance + stable_amount);
     });
     
     // Record the transaction
-    let approve = Approve {
+    let transfer = Transfer {
+        amount: amount.clone(),
         from: from.clone(),
-        spender: spender.clone(),
-        amount,
-        expected_allowance,
-        expires_at,
-        memo,
-        fee: Some(fee.clone()),
+        to: credited,
+        spender: None,
+        memo: memo.clone(),
+        fee: Some(charged_fee.clone()),
         created_at_time,
+        category,
     };
     
-    let tx = Transaction::approve(approve, time());
+    // Credit the tip to the tip account (a burned tip already left the supply above)
+    if let Some(Tip { amount, to: Some(tip_account) }) = &tip {
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            let balance = balances.get(tip_account).unwrap_or_else(|| StableNat::from(0u64));
+            balances.insert(tip_account.clone(), balance + StableNat::from_nat(amount.clone()));
+        });
+    }
+    
+    add_fees_paid(&from, &charged_fee);
+    let fee_collector = collect_fee(&charged_fee);
+    if let Some(fee_debt) = fee_debt {
+        FEE_DEBTS.with(|debts| {
+            debts.borrow_mut().insert(from.clone(), StableNat::from_nat(fee_debt));
+        });
+    }
+    
+    // Draw the amount and fee down from the delegation
+    if let Some(remaining) = remaining_delegation {
+        DELEGATIONS.with(|delegations| {
+            delegations.borrow_mut().insert(delegation_key(&from, caller), remaining);
+        });
+    }
+    
+    let mut tx = Transaction::transfer(transfer, time());
+    tx.tip = tip;
+    tx.fee_collector = fee_collector;
+    tx.fee_implicit = Some(fee_implicit);
     let block_index = record_transaction(tx);
+    remember_transaction(dedup_key, &block_index);
     
-    ApproveResult::Ok(block_index)
+    match dispute_window {
+        Some(window) => {
+            let pending = PendingTransfer {
+                from: from.clone(),
+                to: to.clone(),
+                amount: amount.clone(),
+                final_at: time().saturating_add(window),
+                payment_code: None,
+            };
+            PENDING_TRANSFERS.with(|pending_transfers| {
+                pending_transfers.borrow_mut().insert(StableBlockIndex::from_nat(&block_index), pending);
+            });
+            ic_cdk_timers::set_timer(Duration::from_nanos(window), finalize_due_transfers);
+        }
+        None => consolidate_dust(&to),
+    }
+    
+    TransferResult::Ok(block_index)
 }
 
-// ICRC-2 Allowance
-#[query]
-fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
-    let account = args.account;
-    let spender = args.spender;
-    
-    ALLOWANCES.with(|allowances| {
-        allowances
-            .borrow()
-            .get(&AccountPair(account, spender))
-            .unwrap_or_else(|| Allowance {
-                allowance: Nat::from(0u64),
-                expires_at: None,
-            })
+// icrc1_transfer that also returns the sender's and recipient's balances after the transfer and the fee
+// actually charged (zero when fees are deferred)
+#[update]
+fn icrc1_transfer_detailed(args: TransferArgs) -> DetailedTransferResult {
+    let from = Account {
+        owner: args.on_behalf_of.unwrap_or_else(ic_cdk::caller),
+        subaccount: args.from_subaccount.clone(),
+    }
+    .canonical();
+    let to = args.to.clone().canonical();
+    let block_index = icrc1_transfer(args)?;
+
+    let fee_charged = TRANSACTIONS
+        .with(|txs| txs.borrow().get(&StableBlockIndex::from_nat(&block_index)))
+        .map(|tx| tx.fee())
+        .unwrap_or_else(|| Nat::from(0u64));
+    Ok(DetailedTransfer {
+        block_index,
+        from_balance_after: get_account_balance(&from),
+        to_balance_after: get_account_balance(&to),
+        fee_charged,
     })
 }
 
-// ICRC-2 Transfer From
+// Reverse a transfer still inside its dispute window, returning the amount to the sender (callable by the sender)
+// The fee is not refunded
 #[update]
-fn icrc2_transfer_from(args: TransferFromArgs) -> TransferFromResult {
-    let caller = ic_cdk::caller();
-    let spender = Account {
-        owner: caller,
-        subaccount: args.spender_subaccount,
+fn reverse_transfer(block_index: Nat) -> Result<Nat, String> {
+    let index = StableBlockIndex::from_nat(&block_index);
+    let pending = match PENDING_TRANSFERS.with(|pending_transfers| pending_transfers.borrow().get(&index)) {
+        Some(pending) => pending,
+        None => return Err("No pending transfer at this block index".to_string()),
     };
-    let from = args.from;
-    let to = args.to;
-    let amount = args.amount.clone();
-    let fee = args.fee.unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().fee.clone()));
-    let memo = args.memo;
-    let created_at_time = args.created_at_time;
-    
-    // Validate the transaction
-    if let Some(created_at) = created_at_time {
-        let now = time();
-        if created_at > now {
-            return TransferFromResult::Err(TransferFromError::CreatedInFuture { ledger_time: now });
+
+    if pending.from.owner != ic_cdk::caller() {
+        return Err("Only the sender can reverse a transfer".to_string());
+    }
+    if !pending.is_reversible(time()) {
+        return Err("The dispute window has closed".to_string());
+    }
+
+    Ok(settle_pending_transfer(index, pending, true))
+}
+
+// Accept a pending transfer before its dispute window closes, making it final (callable by the recipient)
+#[update]
+fn accept_transfer(block_index: Nat) -> Result<Nat, String> {
+    let index = StableBlockIndex::from_nat(&block_index);
+    let pending = match PENDING_TRANSFERS.with(|pending_transfers| pending_transfers.borrow().get(&index)) {
+        Some(pending) => pending,
+        None => return Err("No pending transfer at this block index".to_string()),
+    };
+
+    if pending.to.owner != ic_cdk::caller() {
+        return Err("Only the recipient can accept a transfer".to_string());
+    }
+
+    Ok(settle_pending_transfer(index, pending, false))
+}
+
+// Transfer held for the dispute window at the given block index, if still pending
+#[query]
+fn get_pending_transfer(block_index: Nat) -> Option<PendingTransfer> {
+    PENDING_TRANSFERS.with(|pending_transfers| pending_transfers.borrow().get(&StableBlockIndex::from_nat(&block_index)))
+}
+
+// Helper function to release a pending transfer from the dispute account to the recipient or back to the sender
+// Records a transfer block whose memo carries the held transfer's block index as 8 big-endian bytes
+fn settle_pending_transfer(index: StableBlockIndex, pending: PendingTransfer, reversed: bool) -> BlockIndex {
+    PENDING_TRANSFERS.with(|pending_transfers| pending_transfers.borrow_mut().remove(&index));
+
+    // A reversed payment leaves its request unpaid again
+    if let (true, Some(code)) = (reversed, pending.payment_code) {
+        PAYMENT_REQUESTS.with(|requests| {
+            let mut requests = requests.borrow_mut();
+            if let Some(request) = requests.get(&code) {
+                requests.insert(code, PaymentRequest { paid_in: None, ..request });
+            }
+        });
+    }
+
+    let beneficiary = if reversed { pending.from } else { pending.to };
+    pay_out_held_funds(dispute_account(), beneficiary, pending.amount, Some(index.0.to_be_bytes().to_vec()))
+}
+
+// Helper function to get the ledger-owned account holding transfers during the dispute window
+fn dispute_account() -> Account {
+    ledger_account(ic_cdk::id(), DISPUTE_TAG)
+}
+
+// Helper function to get the ledger-owned account holding the funds of open escrows
+fn escrow_account() -> Account {
+    ledger_account(ic_cdk::id(), ESCROW_TAG)
+}
+
+// Helper function to move `amount` held by a ledger-owned account to `to`, recording it as a transfer block
+fn pay_out_held_funds(holder: Account, to: Account, amount: Nat, memo: Option<Vec<u8>>) -> BlockIndex {
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let held = balances.get(&holder).unwrap_or_else(|| StableNat::from(0u64)).into_nat();
+        if held <= amount {
+            balances.remove(&holder);
+        } else {
+            balances.insert(holder.clone(), StableNat::from_nat(held - amount.clone()));
         }
-        if now > created_at + TX_WINDOW {
-            return TransferFromResult::Err(TransferFromError::TooOld);
+        let balance = balances.get(&to).unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(to.clone(), balance + StableNat::from_nat(amount.clone()));
+    });
+
+    let transfer = Transfer {
+        amount,
+        from: holder,
+        to,
+        spender: None,
+        memo,
+        fee: None,
+        created_at_time: None,
+        category: None,
+    };
+    record_transaction(Transaction::transfer(transfer, time()))
+}
+
+// Lock amount plus fee from the caller's default account for `to` until released or refunded
+// The lock is recorded as a transfer into the escrow account carrying the release condition as its memo;
+// its index is the escrow id
+#[update]
+fn create_escrow(to: Account, amount: Nat, release_condition_memo: Option<Vec<u8>>) -> Result<EscrowId, TransferError> {
+    let to = to.canonical();
+    let ttl = match TOKEN_DATA.with(|data| data.borrow().escrow_ttl) {
+        Some(ttl) => ttl,
+        None => return Err(TransferError::TemporarilyUnavailable),
+    };
+
+    if let Err(message) = validate_memo(release_condition_memo.as_deref()) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+
+    let from = get_caller_account();
+    check_sender(&from, &amount)?;
+
+    let fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
+    let from_balance = get_account_balance(&from);
+    let total_deduction = amount.clone() + fee.clone();
+    if from_balance < total_deduction {
+        return Err(TransferError::InsufficientFunds { balance: from_balance });
+    }
+
+    let holder = escrow_account();
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        if from_balance == total_deduction {
+            balances.remove(&from);
+        } else {
+            balances.insert(from.clone(), StableNat::from_nat(from_balance - total_deduction));
         }
+        let held = balances.get(&holder).unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(holder.clone(), held + StableNat::from_nat(amount.clone()));
+    });
+    add_fees_paid(&from, &fee);
+    let fee_collector = collect_fee(&fee);
+
+    let transfer = Transfer {
+        amount: amount.clone(),
+        from: from.clone(),
+        to: holder,
+        spender: None,
+        memo: release_condition_memo,
+        fee: Some(fee),
+        created_at_time: None,
+        category: None,
+    };
+    let now = time();
+    let block_index = record_transaction(Transaction {
+        fee_collector,
+        ..Transaction::transfer(transfer, now)
+    });
+
+    let escrow_id = StableBlockIndex::from_nat(&block_index).0;
+    let escrow = Escrow {
+        from,
+        to,
+        amount,
+        expires_at: now.saturating_add(ttl),
+    };
+    ESCROWS.with(|escrows| escrows.borrow_mut().insert(escrow_id, escrow));
+
+    Ok(escrow_id)
+}
+
+// Release an escrow's funds to the recipient (callable by the sender)
+#[update]
+fn release_escrow(id: EscrowId) -> Result<Nat, String> {
+    let escrow = ESCROWS.with(|escrows| escrows.borrow().get(&id)).ok_or("No open escrow with this id")?;
+    if !escrow.can_release(ic_cdk::caller()) {
+        return Err("Only the sender can release an escrow".to_string());
     }
-    
+
+    Ok(settle_escrow(id, escrow, false))
+}
+
+// Return an escrow's funds to the sender (callable by the recipient, or by the sender once it has expired)
+// The fee paid to create the escrow is not refunded
+#[update]
+fn refund_escrow(id: EscrowId) -> Result<Nat, String> {
+    let escrow = ESCROWS.with(|escrows| escrows.borrow().get(&id)).ok_or("No open escrow with this id")?;
+    if !escrow.can_refund(ic_cdk::caller(), time()) {
+        return Err("Only the recipient, or the sender after expiry, can refund an escrow".to_string());
+    }
+
+    Ok(settle_escrow(id, escrow, true))
+}
+
+// Open escrow with the given id
+#[query]
+fn get_escrow(id: EscrowId) -> Option<Escrow> {
+    ESCROWS.with(|escrows| escrows.borrow().get(&id))
+}
+
+// Helper function to close an escrow, paying its funds out of the escrow account to the recipient (release)
+// or back to the sender (refund); the transfer block's memo carries the escrow id as 8 big-endian bytes
+fn settle_escrow(id: EscrowId, escrow: Escrow, refund: bool) -> BlockIndex {
+    ESCROWS.with(|escrows| escrows.borrow_mut().remove(&id));
+
+    let beneficiary = if refund { escrow.from } else { escrow.to };
+    pay_out_held_funds(escrow_account(), beneficiary, escrow.amount, Some(id.to_be_bytes().to_vec()))
+}
+
+// Helper function to finalize pending transfers whose dispute window has closed, at most MAX_BATCH_SIZE per call
+// Pending transfers are keyed by block index, and a shortened window can make later ones due first
+fn finalize_due_transfers() {
+    let now = time();
+    let due: Vec<(StableBlockIndex, PendingTransfer)> = PENDING_TRANSFERS.with(|pending_transfers| {
+        pending_transfers
+            .borrow()
+            .iter()
+            .filter(|(_, pending)| !pending.is_reversible(now))
+            .take(MAX_BATCH_SIZE)
+            .collect()
+    });
+    // A full batch may leave due transfers behind, so run again right after this one
+    if due.len() == MAX_BATCH_SIZE {
+        ic_cdk_timers::set_timer(Duration::ZERO, finalize_due_transfers);
+    }
+
+    for (index, pending) in due {
+        settle_pending_transfer(index, pending, false);
+    }
+}
+
+// Pay the fees accrued by deferred-fee transfers from one of the caller's accounts in a single block
+#[update]
+fn settle_fees(from_subaccount: Option<Subaccount>) -> TransferResult {
+    let from = Account {
+        owner: ic_cdk::caller(),
+        subaccount: from_subaccount,
+    }
+    .canonical();
+
+    let debt = get_fee_debt(&from);
+    if debt.0.is_zero() {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::NoFeeDebt.to_nat(),
+            message: "No fee debt to settle".to_string(),
+        });
+    }
+
+    let from_balance = get_account_balance(&from);
+    if from_balance < debt {
+        return TransferResult::Err(TransferError::InsufficientFunds { balance: from_balance });
+    }
+
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        if from_balance == debt {
+            balances.remove(&from);
+        } else {
+            balances.insert(from.clone(), StableNat::from_nat(from_balance - debt.clone()));
+        }
+    });
+    FEE_DEBTS.with(|debts| debts.borrow_mut().remove(&from));
+    add_fees_paid(&from, &debt);
+    let fee_collector = collect_fee(&debt);
+
+    let fee_payment = FeePayment { from, amount: debt };
+    TransferResult::Ok(record_transaction(Transaction {
+        fee_collector,
+        ..Transaction::fee_payment(fee_payment, time())
+    }))
+}
+
+// Fees an account has accrued through deferred-fee transfers and not yet settled
+#[query]
+fn fee_debt(account: Account) -> Nat {
+    let account = account.canonical();
+    get_fee_debt(&account)
+}
+
+// Helper function to read an account's outstanding fee debt
+fn get_fee_debt(account: &Account) -> Nat {
+    FEE_DEBTS.with(|debts| {
+        debts.borrow()
+            .get(account)
+            .map(|debt| debt.into_nat())
+            .unwrap_or_else(|| Nat::from(0u64))
+    })
+}
+
+// Batch ICRC-1 Transfer
+// Entries are processed in order against the sender's balance as it stands after the previous entries,
+// so ordering matters; each pays its own fee and failures do not roll back earlier entries
+#[update]
+fn icrc1_batch_transfer(transfers: Vec<TransferArgs>) -> Vec<TransferResult> {
+    transfers
+        .into_iter()
+        .enumerate()
+        .map(|(position, args)| {
+            if position >= MAX_BATCH_SIZE {
+                return TransferResult::Err(TransferError::GenericError {
+                    error_code: ErrorCode::BatchTooLarge.to_nat(),
+                    message: format!("Only the first {} transfers of a batch are processed", MAX_BATCH_SIZE),
+                });
+            }
+            icrc1_transfer(args)
+        })
+        .collect()
+}
+
+// Netted batch transfer between the caller's own accounts and their counterparties
+// Opposite entries between the same two accounts offset each other, so each pair records one transfer of
+// the difference and pays a single fee at the current rate; pairs that cancel exactly record nothing
+// Returns one result per net transfer, in order of each pair's first entry. Net transfers carry no memo,
+// category or tip
+#[update]
+fn net_batch_transfer(transfers: Vec<TransferArgs>) -> Vec<TransferResult> {
+    if !TOKEN_DATA.with(|data| data.borrow().batch_netting_enabled.unwrap_or(false)) {
+        return vec![TransferResult::Err(TransferError::TemporarilyUnavailable)];
+    }
+
+    if transfers.len() > MAX_BATCH_SIZE {
+        return vec![TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::BatchTooLarge.to_nat(),
+            message: format!("A netted batch may hold at most {} transfers", MAX_BATCH_SIZE),
+        })];
+    }
+
+    // Offsetting is only sound when every entry spends the caller's own funds
+    if transfers.iter().any(|args| args.on_behalf_of.is_some()) {
+        return vec![TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::Unauthorized.to_nat(),
+            message: "Netted batches cannot transfer on behalf of another principal".to_string(),
+        })];
+    }
+
+    let caller = ic_cdk::caller();
+    let entries: Vec<(Account, Account, Nat)> = transfers
+        .iter()
+        .map(|args| {
+            let from = Account {
+                owner: caller,
+                subaccount: args.from_subaccount.clone(),
+            }
+            .canonical();
+            (from, args.to.clone().canonical(), args.amount.clone())
+        })
+        .collect();
+
+    net_transfers(&entries)
+        .into_iter()
+        .map(|(position, amount)| {
+            icrc1_transfer(TransferArgs {
+                from_subaccount: transfers[position].from_subaccount.clone(),
+                to: transfers[position].to.clone(),
+                amount,
+                fee: None,
+                memo: None,
+                created_at_time: None,
+                deadline: None,
+                category: None,
+                on_behalf_of: None,
+                tip: None,
+            })
+        })
+        .collect()
+}
+
+// Schedule a transfer from the caller's account to execute at `execute_at`
+// The amount and fee leave the sender's balance now and are refunded if the transfer is cancelled
+#[update]
+fn schedule_transfer(args: TransferArgs, execute_at: u64) -> Result<ScheduleId, TransferError> {
+    if !TOKEN_DATA.with(|data| data.borrow().scheduled_transfers_enabled) {
+        return Err(TransferError::TemporarilyUnavailable);
+    }
+
+    let now = time();
+    if execute_at <= now {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::DeadlinePassed.to_nat(),
+            message: "Scheduled time must be in the future".to_string(),
+        });
+    }
+
+    if let Err(message) = validate_memo(args.memo.as_deref()) {
+        return Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+
+    let from = Account {
+        owner: ic_cdk::caller(),
+        subaccount: args.from_subaccount,
+    }
+    .canonical();
+    check_sender(&from, &args.amount)?;
+
     // Check if the fee is correct
     let expected_fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
+    let fee = args.fee.unwrap_or_else(|| expected_fee.clone());
     if fee != expected_fee {
-        return TransferFromResult::Err(TransferFromError::BadFee { expected_fee });
+        return Err(TransferError::BadFee { expected_fee });
     }
-    
-    // Check if the sender has enough funds
-    let from_balance = get_account_balance(&from);
-    let total_deduction = amount.clone() + fee.clone();
-    if from_balance < total_deduction {
-        return TransferFromResult::Err(TransferFromError::InsufficientFunds { balance: from_balance });
+
+    let scheduled = ScheduledTransfer {
+        from: from.clone(),
+        to: args.to.canonical(),
+        amount: args.amount,
+        fee,
+        memo: args.memo,
+        execute_at,
+    };
+
+    // Lock the amount and fee
+    let balance = get_account_balance(&from);
+    let locked = scheduled.locked_amount();
+    if balance < locked {
+        return Err(TransferError::InsufficientFunds { balance });
+    }
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        if balance == locked {
+            balances.remove(&from);
+        } else {
+            balances.insert(from.clone(), StableNat::from_nat(balance - locked));
+        }
+    });
+
+    let id = TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        let id = data.next_schedule_id;
+        data.next_schedule_id += 1;
+        id
+    });
+    SCHEDULED_TRANSFERS.with(|scheduled_transfers| {
+        scheduled_transfers.borrow_mut().insert((execute_at, id), scheduled);
+    });
+    ic_cdk_timers::set_timer(Duration::from_nanos(execute_at - now), execute_due_transfers);
+
+    Ok(id)
+}
+
+// Cancel a pending scheduled transfer and refund the locked amount and fee (callable by the sender)
+#[update]
+fn cancel_scheduled_transfer(id: ScheduleId) -> Result<(), String> {
+    let (key, scheduled) = match find_scheduled_transfer(id) {
+        Some(entry) => entry,
+        None => return Err("No pending scheduled transfer with this id".to_string()),
+    };
+
+    if scheduled.from.owner != ic_cdk::caller() {
+        return Err("Only the sender can cancel a scheduled transfer".to_string());
+    }
+
+    SCHEDULED_TRANSFERS.with(|scheduled_transfers| scheduled_transfers.borrow_mut().remove(&key));
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.get(&scheduled.from).unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(scheduled.from.clone(), balance + StableNat::from_nat(scheduled.locked_amount()));
+    });
+
+    Ok(())
+}
+
+// Pending scheduled transfer by id (None once executed or cancelled)
+#[query]
+fn get_scheduled_transfer(id: ScheduleId) -> Option<ScheduledTransfer> {
+    find_scheduled_transfer(id).map(|(_, scheduled)| scheduled)
+}
+
+// Helper function to look up a pending scheduled transfer by id (scans the pending set)
+fn find_scheduled_transfer(id: ScheduleId) -> Option<((u64, ScheduleId), ScheduledTransfer)> {
+    SCHEDULED_TRANSFERS.with(|scheduled_transfers| {
+        scheduled_transfers
+            .borrow()
+            .iter()
+            .find(|((_, scheduled_id), _)| *scheduled_id == id)
+    })
+}
+
+// Helper function to deliver scheduled transfers that are due, at most MAX_BATCH_SIZE per call
+// Each delivery records its transfer block at execution time
+fn execute_due_transfers() {
+    let now = time();
+    let due: Vec<((u64, ScheduleId), ScheduledTransfer)> = SCHEDULED_TRANSFERS.with(|scheduled_transfers| {
+        scheduled_transfers
+            .borrow()
+            .iter()
+            .take_while(|(_, scheduled)| scheduled.is_due(now))
+            .take(MAX_BATCH_SIZE)
+            .collect()
+    });
+    // A full batch may leave due transfers behind, so run again right after this one
+    if due.len() == MAX_BATCH_SIZE {
+        ic_cdk_timers::set_timer(Duration::ZERO, execute_due_transfers);
+    }
+
+    for (key, scheduled) in due {
+        SCHEDULED_TRANSFERS.with(|scheduled_transfers| scheduled_transfers.borrow_mut().remove(&key));
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            let balance = balances.get(&scheduled.to).unwrap_or_else(|| StableNat::from(0u64));
+            balances.insert(scheduled.to.clone(), balance + StableNat::from_nat(scheduled.amount.clone()));
+        });
+
+        add_fees_paid(&scheduled.from, &scheduled.fee);
+        let fee_collector = collect_fee(&scheduled.fee);
+
+        let transfer = Transfer {
+            amount: scheduled.amount,
+            from: scheduled.from,
+            to: scheduled.to,
+            spender: None,
+            memo: scheduled.memo,
+            fee: Some(scheduled.fee),
+            created_at_time: None,
+            category: None,
+        };
+        record_transaction(Transaction {
+            fee_collector,
+            ..Transaction::transfer(transfer, now)
+        });
+    }
+}
+
+// Payment request for the caller's default account, payable once with `pay` until it expires
+#[update]
+fn create_payment_request(amount: Nat, memo: Option<Vec<u8>>) -> Result<PaymentCode, String> {
+    let ttl = match TOKEN_DATA.with(|data| data.borrow().payment_request_ttl) {
+        Some(ttl) => ttl,
+        None => return Err("Payment requests are disabled".to_string()),
+    };
+    validate_memo(memo.as_deref())?;
+
+    let code = TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        let code = data.next_payment_code;
+        data.next_payment_code += 1;
+        code
+    });
+
+    let request = PaymentRequest {
+        merchant: get_caller_account(),
+        amount,
+        memo,
+        expires_at: time().saturating_add(ttl),
+        paid_in: None,
+    };
+    PAYMENT_REQUESTS.with(|requests| requests.borrow_mut().insert(code, request));
+
+    Ok(code)
+}
+
+// Pay a payment request from the caller's default account, consuming its code
+#[update]
+fn pay(code: PaymentCode) -> TransferResult {
+    let request = match PAYMENT_REQUESTS.with(|requests| requests.borrow().get(&code)) {
+        Some(request) => request,
+        None => {
+            return TransferResult::Err(TransferError::GenericError {
+                error_code: ErrorCode::PaymentCodeNotFound.to_nat(),
+                message: "Unknown payment code".to_string(),
+            });
+        }
+    };
+    request.check_payable(time())?;
+
+    let block_index = icrc1_transfer(TransferArgs {
+        from_subaccount: None,
+        to: request.merchant.clone(),
+        amount: request.amount.clone(),
+        fee: None,
+        memo: request.memo.clone(),
+        created_at_time: None,
+        deadline: None,
+        category: None,
+        on_behalf_of: None,
+        tip: None,
+    })?;
+
+    PAYMENT_REQUESTS.with(|requests| {
+        requests.borrow_mut().insert(code, PaymentRequest {
+            paid_in: Some(block_index.clone()),
+            ..request
+        });
+    });
+
+    // A payment held for the dispute window reopens the request if the payer reverses it
+    let index = StableBlockIndex::from_nat(&block_index);
+    PENDING_TRANSFERS.with(|pending_transfers| {
+        let mut pending_transfers = pending_transfers.borrow_mut();
+        if let Some(pending) = pending_transfers.get(&index) {
+            pending_transfers.insert(index, PendingTransfer { payment_code: Some(code), ..pending });
+        }
+    });
+
+    TransferResult::Ok(block_index)
+}
+
+// Payment request details, including the block that paid it
+#[query]
+fn get_payment_request(code: PaymentCode) -> Option<PaymentRequest> {
+    PAYMENT_REQUESTS.with(|requests| requests.borrow().get(&code))
+}
+
+// ICRC-2 Approve
+#[update]
+fn icrc2_approve(args: ApproveArgs) -> ApproveResult {
+    // Approvals can be paused independently of transfers
+    if TOKEN_DATA.with(|data| data.borrow().approvals_paused) || method_disabled("icrc2_approve") {
+        return ApproveResult::Err(ApproveError::TemporarilyUnavailable);
+    }
+    sweep_expired_allowances();
+
+    let caller = ic_cdk::caller();
+    let from = Account {
+        owner: caller,
+        subaccount: args.from_subaccount,
+    }
+    .canonical();
+    let spender = args.spender.canonical();
+    let amount = args.amount.clone();
+    let expected_allowance = args.expected_allowance.clone();
+    // Approvals without an expiry get the default one, if configured
+    let expires_at = TOKEN_DATA.with(|data| data.borrow().approval_expiry(args.expires_at, time()));
+    let fee = args.fee.unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().fee.clone()));
+    let memo = args.memo;
+    let created_at_time = args.created_at_time;
+    
+    // Validate the memo length
+    if let Err(message) = validate_memo(memo.as_deref()) {
+        return ApproveResult::Err(ApproveError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+    
+    // Validate the transaction
+    if let Some(created_at) = created_at_time {
+        let now = time();
+        if created_at > now {
+            return ApproveResult::Err(ApproveError::CreatedInFuture { ledger_time: now });
+        }
+        if now > created_at + TX_WINDOW {
+            return ApproveResult::Err(ApproveError::TooOld);
+        }
+    }
+    
+    // Reject a retry of an approval already recorded within the window
+    prune_recent_transactions();
+    let dedup_key = created_at_time.map(|created_at_time| DedupKey {
+        created_at_time,
+        kind: "approve".to_string(),
+        caller,
+        from: from.clone(),
+        to: spender.clone(),
+        amount: amount.clone(),
+        fee: fee.clone(),
+        memo: memo.clone(),
+    });
+    if let Some(duplicate_of) = dedup_key.as_ref().and_then(find_duplicate) {
+        return ApproveResult::Err(ApproveError::Duplicate { duplicate_of });
+    }
+    
+    // Check if the fee is correct
+    let expected_fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
+    if fee != expected_fee {
+        return ApproveResult::Err(ApproveError::BadFee { expected_fee });
+    }
+    
+    // Check the approval is not dust (revocations are always allowed)
+    if TOKEN_DATA.with(|data| data.borrow().approval_below_minimum(&amount)) {
+        return ApproveResult::Err(ApproveError::GenericError {
+            error_code: ErrorCode::ApprovalBelowMinimum.to_nat(),
+            message: "Approval amount is below the minimum".to_string(),
+        });
+    }
+    
+    // Check the approval carries an expiry, if required (revocations are always allowed)
+    if TOKEN_DATA.with(|data| data.borrow().approval_missing_expiry(&amount, expires_at)) {
+        return ApproveResult::Err(ApproveError::GenericError {
+            error_code: ErrorCode::ApprovalExpiryRequired.to_nat(),
+            message: "Approvals must specify an expiry".to_string(),
+        });
+    }
+    
+    // Check if the sender has enough funds for the fee
+    let from_balance = get_account_balance(&from);
+    if from_balance < fee {
+        return ApproveResult::Err(ApproveError::InsufficientFunds { balance: from_balance });
+    }
+    
+    // Check if the current allowance matches the expected allowance
+    if let Some(expected) = &expected_allowance {
+        let current = ALLOWANCES.with(|allowances| {
+            allowances
+                .borrow()
+                .get(&AccountPair(from.clone(), spender.clone()))
+                .map(|a| a.effective(time()).allowance)
+                .unwrap_or_else(|| Nat::from(0u64))
+        });
+        
+        if &current != expected {
+            return ApproveResult::Err(ApproveError::AllowanceChanged { current_allowance: current });
+        }
+    }
+    
+    // Check if the approval has expired
+    if let Some(expires) = expires_at {
+        let now = time();
+        if expires < now {
+            return ApproveResult::Err(ApproveError::Expired { ledger_time: now });
+        }
+    }
+    
+    let allowance = Allowance {
+        allowance: amount.clone(),
+        expires_at,
+    };
+    
+    // An approval that leaves the allowance exactly as it was records no block and charges no fee, if enabled
+    // The index of the pair's last approve block is returned instead
+    let pair = AccountPair(from.clone(), spender.clone());
+    let skip_unchanged = TOKEN_DATA.with(|data| data.borrow().skip_unchanged_approvals);
+    if skip_unchanged && ALLOWANCES.with(|allowances| allowances.borrow().get(&pair)).as_ref() == Some(&allowance) {
+        if let Some(last_block) = LAST_APPROVE_BLOCKS.with(|blocks| blocks.borrow().get(&pair)) {
+            let block_index = last_block.to_nat();
+            remember_transaction(dedup_key, &block_index);
+            return ApproveResult::Ok(block_index);
+        }
+    }
+    
+    // Update balances for the fee
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        
+        // Convert fee to StableNat
+        let fee_clone = fee.clone();
+        let from_balance_clone = from_balance.clone();
+        
+        if from_balance_clone == fee_clone {
+            // If exact fee amount, remove the entry
+            balances.remove(&from);
+        } else {
+            // Otherwise, update with new balance
+            let new_stable_balance = StableNat::from_nat(from_balance_clone - fee_clone);
+            balances.insert(from.clone(), new_stable_balance);
+        }
+    });
+    
+    // Update allowance
+    ALLOWANCES.with(|allowances| {
+        allowances.borrow_mut().insert(pair.clone(), allowance);
+    });
+    
+    // Remember the approved amount so utilization can be reported later
+    APPROVED_AMOUNTS.with(|approved| {
+        approved.borrow_mut().insert(AccountPair(from.clone(), spender.clone()), StableNat::from_nat(amount.clone()));
+    });
+    
+    // Record the transaction
+    let approve = Approve {
+        from: from.clone(),
+        spender: spender.clone(),
+        amount,
+        expected_allowance,
+        expires_at,
+        memo,
+        fee: Some(fee.clone()),
+        created_at_time,
+    };
+    
+    add_fees_paid(&from, &fee);
+    let fee_collector = collect_fee(&fee);
+    
+    // The op is fixed when the block is recorded so the block's hash never changes
+    let tag_revocations = TOKEN_DATA.with(|data| data.borrow().tag_revocations);
+    let tx = Transaction {
+        kind: approve.op(tag_revocations).to_string(),
+        fee_collector,
+        ..Transaction::approve(approve, time())
+    };
+    let block_index = record_transaction(tx);
+    remember_transaction(dedup_key, &block_index);
+    
+    LAST_APPROVE_BLOCKS.with(|blocks| {
+        blocks.borrow_mut().insert(pair, StableBlockIndex::from_nat(&block_index));
+    });
+    
+    ApproveResult::Ok(block_index)
+}
+
+// Add `amount` to the caller's allowance for the spender within a single update, so concurrent
+// transfer_from calls cannot race a read-then-approve; charges the approve fee and records an approve block
+// with the resulting allowance. The current expiry is kept unless expires_at is given
+#[update]
+fn icrc2_increase_allowance(args: ApproveArgs) -> ApproveResult {
+    adjust_allowance(args, true)
+}
+
+// Subtract `amount` from the caller's allowance for the spender, clamping at zero; otherwise as
+// icrc2_increase_allowance
+#[update]
+fn icrc2_decrease_allowance(args: ApproveArgs) -> ApproveResult {
+    adjust_allowance(args, false)
+}
+
+// Helper function to approve the current allowance adjusted by `args.amount`
+fn adjust_allowance(args: ApproveArgs, increase: bool) -> ApproveResult {
+    let current = icrc2_allowance(AllowanceArgs {
+        account: Account {
+            owner: ic_cdk::caller(),
+            subaccount: args.from_subaccount.clone(),
+        },
+        spender: args.spender.clone(),
+    });
+
+    icrc2_approve(ApproveArgs {
+        amount: current.adjusted(&args.amount, increase),
+        expires_at: args.expires_at.or(current.expires_at),
+        ..args
+    })
+}
+
+// Batch ICRC-2 Approve
+// Entries are processed in order, each paying its own fee; failures do not roll back earlier entries
+// An entry identical to one already accepted in the batch is reported as a duplicate of its block
+#[update]
+fn icrc2_batch_approve(approvals: Vec<ApproveArgs>) -> Vec<ApproveResult> {
+    let mut accepted: Vec<(ApproveArgs, Nat)> = Vec::new();
+    let mut results = Vec::with_capacity(approvals.len());
+
+    for (position, args) in approvals.into_iter().enumerate() {
+        if position >= MAX_BATCH_SIZE {
+            results.push(ApproveResult::Err(ApproveError::GenericError {
+                error_code: ErrorCode::BatchTooLarge.to_nat(),
+                message: format!("Only the first {} approvals of a batch are processed", MAX_BATCH_SIZE),
+            }));
+            continue;
+        }
+
+        if let Some(duplicate_of) = duplicate_in_batch(&accepted, &args) {
+            results.push(ApproveResult::Err(ApproveError::Duplicate { duplicate_of }));
+            continue;
+        }
+
+        let result = icrc2_approve(args.clone());
+        if let Ok(block_index) = &result {
+            accepted.push((args, block_index.clone()));
+        }
+        results.push(result);
+    }
+
+    results
+}
+
+// ICRC-2 Allowance
+#[query]
+fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
+    let account = args.account.canonical();
+    let spender = args.spender.canonical();
+    
+    ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .get(&AccountPair(account, spender))
+            .map(|allowance| allowance.effective(time()))
+            .unwrap_or_else(|| Allowance {
+                allowance: Nat::from(0u64),
+                expires_at: None,
+            })
+    })
+}
+
+// Helper function to reclaim storage from expired allowances
+// Examines the next MAX_BATCH_SIZE pairs after the cursor per call, wrapping around at the end of the map
+fn sweep_expired_allowances() {
+    let now = time();
+    let start = match ALLOWANCE_SWEEP_CURSOR.with(|cursor| cursor.borrow_mut().take()) {
+        Some(pair) => Bound::Excluded(pair),
+        None => Bound::Unbounded,
+    };
+
+    let batch: Vec<(AccountPair, Allowance)> = ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .range((start, Bound::Unbounded))
+            .take(MAX_BATCH_SIZE)
+            .collect()
+    });
+    if batch.len() == MAX_BATCH_SIZE {
+        let last = batch.last().map(|(pair, _)| pair.clone());
+        ALLOWANCE_SWEEP_CURSOR.with(|cursor| *cursor.borrow_mut() = last);
+    }
+
+    // The approved amount and last approve block of an expired pair go with it
+    for (pair, allowance) in batch {
+        if allowance.is_expired(now) {
+            ALLOWANCES.with(|allowances| allowances.borrow_mut().remove(&pair));
+            APPROVED_AMOUNTS.with(|approved| approved.borrow_mut().remove(&pair));
+            LAST_APPROVE_BLOCKS.with(|blocks| blocks.borrow_mut().remove(&pair));
+        }
+    }
+}
+
+// (owner balance, unexpired allowance granted to spender) in one call for spend UIs
+// The spender can move at most min(balance, allowance) minus the fee right now
+#[query]
+fn spendable_via(owner: Account, spender: Account) -> (Nat, Nat) {
+    let owner = owner.canonical();
+    let spender = spender.canonical();
+    let balance = get_account_balance(&owner);
+    let allowance = icrc2_allowance(AllowanceArgs {
+        account: owner,
+        spender,
+    });
+    (balance, allowance.allowance)
+}
+
+// How much of an allowance has been used: (spent via transfer_from so far, amount originally approved)
+#[query]
+fn allowance_utilization(owner: Account, spender: Account) -> (Nat, Nat) {
+    let owner = owner.canonical();
+    let spender = spender.canonical();
+    let pair = AccountPair(owner, spender);
+    let approved = APPROVED_AMOUNTS.with(|approved| {
+        approved
+            .borrow()
+            .get(&pair)
+            .map(|amount| amount.into_nat())
+            .unwrap_or_else(|| Nat::from(0u64))
+    });
+    let remaining = ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .get(&pair)
+            .map(|a| a.allowance)
+            .unwrap_or_else(|| Nat::from(0u64))
+    });
+
+    let spent = if approved > remaining {
+        approved.clone() - remaining
+    } else {
+        Nat::from(0u64)
+    };
+
+    (spent, approved)
+}
+
+// (sum of all unexpired allowances, total supply); a first value above the second signals over-approval
+// Iterates every stored allowance
+#[query]
+fn allowance_exposure() -> (Nat, Nat) {
+    let now = time();
+    let committed = ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .iter()
+            .filter(|(_, allowance)| !allowance.is_expired(now))
+            .fold(Nat::from(0u64), |sum, (_, allowance)| sum + allowance.allowance)
+    });
+    let total_supply = TOKEN_DATA.with(|data| data.borrow().total_supply.clone());
+
+    (committed, total_supply)
+}
+
+// Page count of each allocated stable memory (callable by a canister controller)
+// Ids: 0 balances, 1 allowances, 2 transactions; the rest are listed where each map is declared
+#[query]
+fn stable_layout() -> Result<Vec<(u8, u64)>, String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can inspect the stable memory layout".to_string());
+    }
+
+    Ok(MEMORY_MANAGER.with(|m| memory_layout(&m.borrow())))
+}
+
+// Export allowances in key order for backup or migration (callable by a canister controller)
+#[query]
+fn export_allowances(start_after: Option<AccountPair>, limit: u32) -> Result<Vec<(Account, Account, Allowance)>, String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can export allowances".to_string());
+    }
+
+    let limit = std::cmp::min(limit, MAX_PAGE_SIZE) as usize;
+    let start = match start_after {
+        Some(pair) => Bound::Excluded(pair),
+        None => Bound::Unbounded,
+    };
+
+    Ok(ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(pair, allowance)| (pair.0, pair.1, allowance))
+            .collect()
+    }))
+}
+
+// (owner, spender, amount) edges of the approval graph in key order (callable by a canister controller)
+// Expired and zero allowances are skipped; pass the last returned pair as start_after for the next page
+#[query]
+fn allowance_edges(start_after: Option<AccountPair>, limit: u32) -> Result<Vec<(Account, Account, Nat)>, String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can list allowance edges".to_string());
+    }
+
+    let now = time();
+    let limit = std::cmp::min(limit, MAX_PAGE_SIZE) as usize;
+    let start = match start_after {
+        Some(pair) => Bound::Excluded(pair),
+        None => Bound::Unbounded,
+    };
+
+    Ok(ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .range((start, Bound::Unbounded))
+            .filter(|(_, allowance)| !allowance.is_expired(now) && allowance.allowance > 0u64)
+            .take(limit)
+            .map(|(pair, allowance)| (pair.0, pair.1, allowance.allowance))
+            .collect()
+    }))
+}
+
+// Import allowances exported from another ledger, at most MAX_BATCH_SIZE per call (callable by a canister controller)
+// Existing entries for the same (owner, spender) pair are overwritten and expired entries are skipped
+// The imported amount counts as the approved amount; no approve block on this ledger backs it
+#[update]
+fn import_allowances(entries: Vec<(Account, Account, Allowance)>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can import allowances".to_string());
+    }
+    if entries.len() > MAX_BATCH_SIZE {
+        return Err(format!("At most {} allowances can be imported per call", MAX_BATCH_SIZE));
+    }
+
+    let now = time();
+    for (owner, spender, allowance) in entries {
+        if allowance.is_expired(now) {
+            continue;
+        }
+        let pair = AccountPair(owner.canonical(), spender.canonical());
+        APPROVED_AMOUNTS.with(|approved| {
+            approved.borrow_mut().insert(pair.clone(), StableNat::from_nat(allowance.allowance.clone()));
+        });
+        LAST_APPROVE_BLOCKS.with(|blocks| blocks.borrow_mut().remove(&pair));
+        ALLOWANCES.with(|allowances| allowances.borrow_mut().insert(pair, allowance));
+    }
+
+    Ok(())
+}
+
+// ICRC-2 Transfer From
+#[update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> TransferFromResult {
+    if method_disabled("icrc2_transfer_from") {
+        return TransferFromResult::Err(TransferFromError::TemporarilyUnavailable);
+    }
+    sweep_expired_allowances();
+
+    let caller = ic_cdk::caller();
+    let spender = Account {
+        owner: caller,
+        subaccount: args.spender_subaccount,
+    }
+    .canonical();
+    let from = args.from.canonical();
+    let to = args.to.canonical();
+    let amount = args.amount.clone();
+    let fee = args.fee.unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().fee.clone()));
+    let memo = args.memo;
+    let created_at_time = args.created_at_time;
+    
+    // Validate the memo length
+    if let Err(message) = validate_memo(memo.as_deref()) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+    
+    // Reject zero-amount transfers
+    if let Err(message) = validate_amount(&amount) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::InvalidAmount.to_nat(),
+            message,
+        });
+    }
+    
+    // The minimum burn amount also applies to transfers into the minting account
+    if let Err(min_burn_amount) = TOKEN_DATA.with(|data| data.borrow().check_min_burn_to(&to, &amount)) {
+        return TransferFromResult::Err(TransferFromError::BadBurn { min_burn_amount });
+    }
+    
+    // Validate the transaction
+    if let Some(created_at) = created_at_time {
+        let now = time();
+        if created_at > now {
+            return TransferFromResult::Err(TransferFromError::CreatedInFuture { ledger_time: now });
+        }
+        if now > created_at + TX_WINDOW {
+            return TransferFromResult::Err(TransferFromError::TooOld);
+        }
+    }
+    
+    // Reject a retry of a transfer already recorded within the window
+    prune_recent_transactions();
+    let dedup_key = created_at_time.map(|created_at_time| DedupKey {
+        created_at_time,
+        kind: "transfer_from".to_string(),
+        caller,
+        from: from.clone(),
+        to: to.clone(),
+        amount: amount.clone(),
+        fee: fee.clone(),
+        memo: memo.clone(),
+    });
+    if let Some(duplicate_of) = dedup_key.as_ref().and_then(find_duplicate) {
+        return TransferFromResult::Err(TransferFromError::Duplicate { duplicate_of });
+    }
+    
+    // Check if the fee is correct
+    let expected_fee = TOKEN_DATA.with(|data| data.borrow().fee.clone());
+    if fee != expected_fee {
+        return TransferFromResult::Err(TransferFromError::BadFee { expected_fee });
+    }
+
+    // Reject transfers where the fee would eat the whole amount, if enabled
+    if TOKEN_DATA.with(|data| data.borrow().fee_exceeds_amount(&amount)) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::FeeExceedsAmount.to_nat(),
+            message: "Transfer amount must exceed the fee".to_string(),
+        });
+    }
+
+    // Enforce the transfer limit of the sender's KYC tier
+    if exceeds_kyc_limit(&from, &amount) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::KycLimitExceeded.to_nat(),
+            message: "Amount exceeds the transfer limit of the sender's KYC tier".to_string(),
+        });
+    }
+
+    // Block senders whose reputation is below the minimum
+    if below_min_reputation(&from) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::ReputationTooLow.to_nat(),
+            message: "Sender's reputation is below the minimum required to transfer".to_string(),
+        });
+    }
+    
+    // Check if the sender has enough funds
+    let from_balance = get_account_balance(&from);
+    let total_deduction = amount.clone() + fee.clone();
+    if from_balance < total_deduction {
+        return TransferFromResult::Err(TransferFromError::InsufficientFunds { balance: from_balance });
+    }
+    
+    // Check if the recipient may open another subaccount
+    if exceeds_subaccount_limit(&to) {
+        return TransferFromResult::Err(TransferFromError::GenericError {
+            error_code: ErrorCode::SubaccountLimitReached.to_nat(),
+            message: "Recipient has reached the maximum number of subaccounts".to_string(),
+        });
+    }
+    
+    // Check allowance
+    let allowance = ALLOWANCES.with(|allowances| {
+        allowances
+            .borrow()
+            .get(&AccountPair(from.clone(), spender.clone()))
+            .unwrap_or_else(|| Allowance {
+                allowance: Nat::from(0u64),
+                expires_at: None,
+            })
+    });
+    
+    // Check if the allowance has expired
+    if let Some(expires_at) = allowance.expires_at {
+        if expires_at < time() {
+            return TransferFromResult::Err(TransferFromError::InsufficientAllowance {
+                allowance: Nat::from(0u64),
+            });
+        }
+    }
+    
+    // Check if the allowance is sufficient
+    if allowance.allowance < amount {
+        return TransferFromResult::Err(TransferFromError::InsufficientAllowance {
+            allowance: allowance.allowance,
+        });
+    }
+    
+    // Update balances
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        
+        // Convert Nat to StableNat for storage
+        let stable_amount = StableNat::from_nat(amount.clone());
+        let total_deduction_clone = total_deduction.clone();
+        let from_balance_clone = from_balance.clone();
+        
+        // Deduct from sender
+        if from_balance_clone == total_deduction_clone {
+            // If exact amount, remove the entry
+            balances.remove(&from);
+        } else {
+            // Otherwise, update with new balance
+            let new_stable_balance = StableNat::from_nat(from_balance_clone - total_deduction_clone);
+            balances.insert(from.clone(), new_stable_balance);
+        }
+        
+        // Add to recipient
+        let stable_to_balance = balances.get(&to)
+            .unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(to.clone(), stable_to_balance + stable_amount);
+    });
+    
+    // Update allowance
+    let allowance_clone = allowance.allowance.clone();
+    let amount_clone = amount.clone();
+    let new_allowance = allowance_clone - amount_clone;
+    
+    ALLOWANCES.with(|allowances| {
+        let mut allowances = allowances.borrow_mut();
+        if new_allowance == Nat::from(0u64) {
+            allowances.remove(&AccountPair(from.clone(), spender.clone()));
+        } else {
+            allowances.insert(
+                AccountPair(from.clone(), spender.clone()),
+                Allowance {
+                    allowance: new_allowance,
+                    expires_at: allowance.expires_at,
+                },
+            );
+        }
+    });
+    
+    // Record the transaction
+    let transfer = Transfer {
+        amount: amount.clone(),
+        from: from.clone(),
+        to: to.clone(),
+        spender: Some(spender.clone()),
+        memo,
+        fee: Some(fee.clone()),
+        created_at_time,
+        category: None,
+    };
+    
+    add_fees_paid(&from, &fee);
+    let fee_collector = collect_fee(&fee);
+    
+    let tx = Transaction {
+        fee_collector,
+        ..Transaction::transfer(transfer, time())
+    };
+    let block_index = record_transaction(tx);
+    remember_transaction(dedup_key, &block_index);
+    consolidate_dust(&to);
+    
+    TransferFromResult::Ok(block_index)
+}
+
+// ICRC-3 Get Blocks
+// At most max_blocks_per_request (and never more than MAX_GET_BLOCKS_LENGTH) blocks are returned, counting
+// archived and local blocks together; log_length reports the full log so clients can page through the rest
+#[query]
+fn icrc3_get_blocks(args: GetBlocksArgs) -> GetBlocksResult {
+    let start = args.start.0.to_u64().unwrap_or(u64::MAX);
+    let length = args.length.0.to_u64().unwrap_or(u64::MAX);
+    let (log_length, cap) = TOKEN_DATA.with(|data| {
+        let data = data.borrow();
+        (data.next_block_index.clone(), data.max_blocks_per_request)
+    });
+    let first_local = StableBlockIndex::from_nat(&first_available_block()).0;
+
+    // Blocks below first_local have no archive canister to serve them yet, so only the local range is returned
+    let cap = get_blocks_cap(cap);
+    let (_archived, local) = split_block_request(start, length, first_local, StableBlockIndex::from_nat(&log_length).0, Some(cap));
+    let blocks = match local {
+        Some((start, length)) => TRANSACTIONS.with(|txs| {
+            txs.borrow()
+                .range(StableBlockIndex::new(start)..StableBlockIndex::new(start + length))
+                .map(|(index, tx)| BlockWithId {
+                    id: index.to_nat(),
+                    block: transaction_to_value(&tx),
+                })
+                .collect()
+        }),
+        None => Vec::new(),
+    };
+    
+    GetBlocksResult {
+        log_length,
+        blocks,
+        archived_blocks: Vec::new(), // No archived blocks in this implementation
+    }
+}
+
+// Health check of the hash chain: re-hashes the last SELF_CHECK_BLOCKS blocks, checks each links to its
+// predecessor through phash, and that the newest block hashes to the recorded tip
+#[query]
+fn icrc3_self_check() -> bool {
+    let tip = TOKEN_DATA.with(|data| data.borrow().last_block_hash.clone());
+    let blocks: Vec<Value> = TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let end = txs.last_key_value().map(|(index, _)| index.0 + 1).unwrap_or(0);
+        txs.range(StableBlockIndex::new(end.saturating_sub(SELF_CHECK_BLOCKS))..)
+            .map(|(_, tx)| transaction_to_value(&tx))
+            .collect()
+    });
+
+    verify_chain_tail(&blocks, tip.as_deref())
+}
+
+// Certificate for the last block index and hash, verifiable against the IC root key
+// Returns None when the log is empty or when called as an update, where no certificate is available
+#[query]
+fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let (last_block_index, last_block_hash) = TOKEN_DATA.with(|data| data.borrow().tip())?;
+    Some(DataCertificate {
+        certificate,
+        hash_tree: encode_tip_tree(last_block_index, &last_block_hash),
+    })
+}
+
+// Lowest block index still stored locally (0 unless earlier blocks were removed)
+#[query]
+fn first_available_block() -> Nat {
+    TRANSACTIONS.with(|txs| {
+        txs.borrow()
+            .first_key_value()
+            .map(|(index, _)| index.to_nat())
+            .unwrap_or_else(|| TOKEN_DATA.with(|data| data.borrow().next_block_index.clone()))
+    })
+}
+
+// (first, last) index of the blocks whose timestamps fall in [from_ts, to_ts], or (None, None) if there are none
+// Block timestamps never decrease, so both ends are found by binary search; fetch the range with icrc3_get_blocks
+#[query]
+fn block_range_for_time(from_ts: u64, to_ts: u64) -> (Option<Nat>, Option<Nat>) {
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let (start, end) = match (txs.first_key_value(), txs.last_key_value()) {
+            (Some((first, _)), Some((last, _))) => (first.0, last.0 + 1),
+            _ => return (None, None),
+        };
+        let timestamp_at = |index: u64| txs.get(&StableBlockIndex(index)).map(|tx| tx.timestamp).unwrap_or(u64::MAX);
+
+        let first = first_index_where(start, end, |index| timestamp_at(index) >= from_ts);
+        let past_last = first_index_where(first, end, |index| timestamp_at(index) > to_ts);
+        if first < past_last {
+            (Some(Nat::from(first)), Some(Nat::from(past_last - 1)))
+        } else {
+            (None, None)
+        }
+    })
+}
+
+// ICRC-3 Supported Block Types
+#[query]
+fn icrc3_supported_block_types() -> Vec<BlockType> {
+    supported_block_types()
+}
+
+// ICRC-3 Get Archives
+// Blocks are not archived yet, so the only entry is this ledger and the range it stores
+#[query]
+fn icrc3_get_archives(args: GetArchivesArgs) -> Vec<ArchiveInfo> {
+    let next_block_index = TOKEN_DATA.with(|data| data.borrow().next_block_index.clone());
+    ledger_archives(ic_cdk::id(), &first_available_block(), &next_block_index, args.from)
+}
+
+// (timestamp of the first stored block, nanoseconds since the last block) for liveness monitoring
+// A steadily growing gap on a busy ledger may mean it is stuck
+#[query]
+fn activity_health() -> (u64, u64) {
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let genesis = txs.first_key_value().map(|(_, tx)| tx.timestamp).unwrap_or(0);
+        let last = txs.last_key_value().map(|(_, tx)| tx.timestamp);
+        (genesis, activity_gap(time(), last))
+    })
+}
+
+// Number of transactions in each of the last `count` intervals of `interval_ns`, oldest first
+// Only the most recent MAX_SCAN_BLOCKS blocks are scanned
+#[query]
+fn transactions_per_interval(interval_ns: u64, count: u32) -> Vec<u64> {
+    let now = time();
+    let mut counts = vec![0u64; count as usize];
+
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        let log_length = txs.len();
+        let start = log_length.saturating_sub(MAX_SCAN_BLOCKS);
+
+        for (_, tx) in txs.range(StableBlockIndex::new(start)..) {
+            if let Some(bucket) = interval_bucket(now, interval_ns, count, tx.timestamp) {
+                counts[bucket] += 1;
+            }
+        }
+    });
+
+    counts
+}
+
+// Helper function to collect the blocks in [start, start + length) that match `filter`
+// At most MAX_SCAN_BLOCKS blocks are scanned per call to keep queries within instruction limits
+fn scan_blocks(start: Nat, length: Nat, filter: impl Fn(&Transaction) -> bool) -> Vec<BlockWithId> {
+    let start = start.0.to_u64().unwrap_or(u64::MAX);
+    let length = std::cmp::min(length.0.to_u64().unwrap_or(u64::MAX), MAX_SCAN_BLOCKS);
+    let end = start.saturating_add(length);
+
+    TRANSACTIONS.with(|txs| {
+        txs.borrow()
+            .range(StableBlockIndex::new(start)..StableBlockIndex::new(end))
+            .filter(|(_, tx)| filter(tx))
+            .map(|(index, tx)| BlockWithId {
+                id: index.to_nat(),
+                block: transaction_to_value(&tx),
+            })
+            .collect()
+    })
+}
+
+// Largest balance the ledger can store under its balance encoding; mints that could exceed it are rejected
+#[query]
+fn max_balance() -> Nat {
+    StableNat::max_value()
+}
+
+// Block index and amount of the largest transfer, mint or burn recorded, kept up to date as blocks are added
+#[query]
+fn largest_transaction() -> Option<(Nat, Nat)> {
+    TOKEN_DATA.with(|data| data.borrow().largest_transaction.clone())
+}
+
+// Helper function to load the transaction at a block index, if one is recorded there
+fn transaction_at(index: &Nat) -> Option<Transaction> {
+    let index = StableBlockIndex::try_from_nat(index)?;
+    TRANSACTIONS.with(|txs| txs.borrow().get(&index))
+}
+
+// The single block at `index`, or None if no block is recorded there
+// Lets explorers follow a duplicate_of or phash reference without a get_blocks range
+#[query]
+fn get_transaction(index: Nat) -> Option<BlockWithId> {
+    let tx = transaction_at(&index)?;
+    Some(BlockWithId {
+        id: index,
+        block: transaction_to_value(&tx),
+    })
+}
+
+// The block at `index` plus up to `radius` blocks on either side, clamped to the log bounds
+#[query]
+fn get_block_context(index: Nat, radius: u32) -> Vec<BlockWithId> {
+    let (start, length) = context_range(index.0.to_u64().unwrap_or(u64::MAX), radius);
+    scan_blocks(Nat::from(start), Nat::from(length), |_| true)
+}
+
+// Blocks in [start, start + length) tagged with the current log tip, ETag style
+// Pollers pass the tip they last saw and get no blocks back while the log is unchanged
+#[query]
+fn get_blocks_versioned(start: Nat, length: Nat, known_tip: Option<Vec<u8>>) -> GetBlocksVersioned {
+    let tip = TOKEN_DATA.with(|data| log_tip_tag(&data.borrow().next_block_index));
+    if known_tip.as_ref() == Some(&tip) {
+        return GetBlocksVersioned { tip, blocks: None };
+    }
+
+    GetBlocksVersioned {
+        tip,
+        blocks: Some(scan_blocks(start, length, |_| true)),
+    }
+}
+
+// Blocks in [start, start + length) rendered as JSON strings for indexers that do not speak ICRC-3 Value
+// Principals are rendered as text, blobs as hex and numbers as decimal strings
+#[query]
+fn get_blocks_json(start: Nat, length: Nat) -> Vec<(Nat, String)> {
+    scan_blocks(start, length, |_| true)
+        .into_iter()
+        .map(|block| (block.id, block.block.to_json()))
+        .collect()
+}
+
+// Blocks whose memo equals `memo` exactly among the blocks in [start, start + length)
+// Useful for finding the payment carrying a given invoice id
+#[query]
+fn find_blocks_by_memo(memo: Vec<u8>, start: Nat, length: Nat) -> Vec<BlockWithId> {
+    scan_blocks(start, length, |tx| tx.memo() == Some(&memo))
+}
+
+// Canonical memo bytes for structured key-values, ready to pass as the memo of a transfer
+#[query]
+fn encode_structured_memo(entries: Vec<(String, Value)>) -> Result<Vec<u8>, String> {
+    types::encode_structured_memo(&entries)
+}
+
+// Key-values carried by the memo of a block, or None if the block has no structured memo
+#[query]
+fn decode_structured_memo(index: Nat) -> Option<Vec<(String, Value)>> {
+    let tx = transaction_at(&index)?;
+    types::decode_structured_memo(tx.memo()?)
+}
+
+// Transfer blocks tagged with `category` among the blocks in [start, start + length)
+#[query]
+fn get_blocks_by_category(category: String, start: Nat, length: Nat) -> Vec<BlockWithId> {
+    scan_blocks(start, length, |tx| {
+        tx.transfer
+            .as_ref()
+            .and_then(|transfer| transfer.category.as_ref())
+            .map(|tag| tag == &category)
+            .unwrap_or(false)
+    })
+}
+
+// Transfer blocks moving more than `threshold` among the blocks in [start, start + length)
+// Scans up to MAX_SCAN_BLOCKS blocks; page through longer ranges with successive calls
+#[query]
+fn recent_large_transfers(threshold: Nat, start: Nat, length: Nat) -> Vec<BlockWithId> {
+    scan_blocks(start, length, |tx| {
+        tx.transfer
+            .as_ref()
+            .map(|transfer| transfer.amount > threshold)
+            .unwrap_or(false)
+    })
+}
+
+// Up to `length` blocks involving exactly this (owner, subaccount) account from block `start` onward
+// Other subaccounts of the same principal are not included; served from the per-account index
+#[query]
+fn get_subaccount_transactions(owner: Principal, subaccount: Option<Vec<u8>>, start: Nat, length: Nat) -> Vec<BlockWithId> {
+    get_account_transactions(Account { owner, subaccount }, Some(start), length)
+}
+
+// Up to `length` blocks touching `account` (as sender, recipient, spender, ...) from block `start` onward
+// Served from the per-account index; pass the last returned id + 1 as `start` for the next page
+#[query]
+fn get_account_transactions(account: Account, start: Option<Nat>, length: Nat) -> Vec<BlockWithId> {
+    let account = account.canonical();
+    let start = start.map(|start| start.0.to_u64().unwrap_or(u64::MAX)).unwrap_or(0);
+    let length = std::cmp::min(length.0.to_u64().unwrap_or(u64::MAX), MAX_PAGE_SIZE as u64) as usize;
+    let block_indices = ACCOUNT_BLOCKS.with(|index| account_blocks(&index.borrow(), &account, start, length));
+
+    TRANSACTIONS.with(|txs| {
+        let txs = txs.borrow();
+        block_indices
+            .into_iter()
+            .filter_map(|index| {
+                txs.get(&StableBlockIndex::new(index)).map(|tx| BlockWithId {
+                    id: Nat::from(index),
+                    block: transaction_to_value(&tx),
+                })
+            })
+            .collect()
+    })
+}
+
+// Custom mint function (only callable by the minting account)
+#[update]
+fn mint(to: Account, amount: Nat, created_at_time: Option<u64>, memo: Option<Vec<u8>>) -> TransferResult {
+    let to = to.canonical();
+    if method_disabled("mint") {
+        return TransferResult::Err(TransferError::TemporarilyUnavailable);
+    }
+
+    let caller = ic_cdk::caller();
+    let minting_account = TOKEN_DATA.with(|data| data.borrow().minting_account.clone());
+    
+    // Check if the caller is the minting account
+    if minting_account.is_none() || minting_account.as_ref().unwrap().owner != caller {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::Unauthorized.to_nat(),
+            message: "Only the minting account can mint tokens".to_string(),
+        });
+    }
+    
+    // Minting stays off for good once disabled
+    if TOKEN_DATA.with(|data| data.borrow().minting_disabled) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::MintingDisabled.to_nat(),
+            message: "Minting has been permanently disabled".to_string(),
+        });
+    }
+    
+    // Validate the memo length
+    if let Err(message) = validate_memo(memo.as_deref()) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidMemo.to_nat(),
+            message,
+        });
+    }
+    
+    // Reject zero-amount mints
+    if let Err(message) = validate_amount(&amount) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidAmount.to_nat(),
+            message,
+        });
+    }
+    
+    // Validate the transaction
+    if let Some(created_at) = created_at_time {
+        let now = time();
+        if created_at > now {
+            return TransferResult::Err(TransferError::CreatedInFuture { ledger_time: now });
+        }
+        if now > created_at + TX_WINDOW {
+            return TransferResult::Err(TransferError::TooOld);
+        }
+    }
+    
+    // A retry of a mint already recorded within the window returns the original block instead of minting again
+    prune_recent_transactions();
+    let minting_account = minting_account.unwrap();
+    let dedup_key = created_at_time
+        .map(|created_at_time| DedupKey::mint(created_at_time, &minting_account, &to, &amount, memo.clone()));
+    if let Some(duplicate_of) = dedup_key.as_ref().and_then(find_duplicate) {
+        return TransferResult::Ok(duplicate_of);
+    }
+    
+    // Keep every balance within what stable storage can encode
+    if TOKEN_DATA.with(|data| data.borrow().mint_exceeds_max_balance(&amount)) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::BalanceOverflow.to_nat(),
+            message: "Mint would push the supply past the maximum storable balance".to_string(),
+        });
+    }
+    
+    // Respect the supply cap, if configured
+    if TOKEN_DATA.with(|data| data.borrow().exceeds_max_supply(&amount)) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::MaxSupplyExceeded.to_nat(),
+            message: "Mint would push the total supply past the maximum supply".to_string(),
+        });
+    }
+    
+    // Check if the recipient may open another subaccount
+    if exceeds_subaccount_limit(&to) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::SubaccountLimitReached.to_nat(),
+            message: "Recipient has reached the maximum number of subaccounts".to_string(),
+        });
+    }
+
+    // The mint must cover the mint fee, if configured
+    let mint_fee = TOKEN_DATA.with(|data| data.borrow().mint_fee.clone());
+    if let Some(fee) = &mint_fee {
+        if amount <= *fee {
+            return TransferResult::Err(TransferError::GenericError {
+                error_code: ErrorCode::FeeExceedsAmount.to_nat(),
+                message: "Mint amount must exceed the mint fee".to_string(),
+            });
+        }
+    }
+
+    // Split off the mint fee and treasury allocation, if configured
+    let (treasury_account, (recipient_amount, treasury_amount), fee_collector) = TOKEN_DATA.with(|data| {
+        let data = data.borrow();
+        (data.treasury_account.clone(), data.mint_split(&amount), data.mint_fee_collector())
+    });
+
+    if let Some(treasury_account) = treasury_account {
+        if !treasury_amount.0.is_zero() {
+            mint_to(&treasury_account, treasury_amount, None, None, None, None);
+        }
+    }
+
+    let block_index = mint_to(&to, recipient_amount, mint_fee, fee_collector, memo, created_at_time);
+    remember_transaction(dedup_key, &block_index);
+
+    TransferResult::Ok(block_index)
+}
+
+// Helper function to credit newly minted tokens and record the mint block
+// With a fee collector, the fee is minted to it as well and the collector is recorded in the block
+// Blocks without a client-supplied created_at_time are stamped with the current time
+fn mint_to(
+    to: &Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    fee_collector: Option<Account>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> BlockIndex {
+    if let (Some(fee), Some(collector)) = (&fee, &fee_collector) {
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            let balance = balances.get(collector).unwrap_or_else(|| StableNat::from(0u64));
+            balances.insert(collector.clone(), balance + StableNat::from_nat(fee.clone()));
+        });
+        TOKEN_DATA.with(|data| data.borrow_mut().total_supply += fee.clone());
+    }
+
+    // Convert Nat to StableNat for storage
+    let stable_amount = StableNat::from_nat(amount.clone());
+    
+    // Update the recipient's balance
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let stable_balance = balances.get(to)
+            .unwrap_or_else(|| StableNat::from(0u64));
+        balances.insert(to.clone(), stable_balance + stable_amount);
+    });
+    
+    // Update total supply
+    let amount_clone = amount.clone();
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.total_supply += amount_clone;
+    });
+    
+    // Record the transaction
+    let mint = Mint {
+        amount,
+        to: to.clone(),
+        memo,
+        created_at_time: Some(created_at_time.unwrap_or_else(time)),
+        fee,
+    };
+    
+    let mut tx = Transaction::mint(mint, time());
+    tx.fee_collector = fee_collector;
+    record_transaction(tx)
+}
+
+// Function to set the smallest amount that may be burned (callable by a canister controller)
+// Applies to burn and to transfers into the minting account; zero removes the minimum
+#[update]
+fn set_min_burn_amount(min_burn_amount: Nat) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the minimum burn amount".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().min_burn_amount = Some(min_burn_amount);
+    });
+
+    Ok(())
+}
+
+// Function to cap the total supply mints may reach (callable by a canister controller)
+// A cap below the current supply blocks further mints without affecting existing balances
+#[update]
+fn set_max_supply(max_supply: Option<Nat>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the maximum supply".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().max_supply = max_supply;
+    });
+
+    Ok(())
+}
+
+// Function to route a share of every mint to a treasury account (callable by a canister controller)
+#[update]
+fn set_treasury(treasury_account: Option<Account>, treasury_bps: u16) -> Result<(), String> {
+    let treasury_account = treasury_account.map(Account::canonical);
+    if !caller_is_controller() {
+        return Err("Only a controller can set the treasury".to_string());
+    }
+
+    if treasury_bps > 10_000 {
+        return Err("Treasury share cannot exceed 10000 basis points".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.treasury_account = treasury_account;
+        data.treasury_bps = treasury_bps;
+    });
+
+    Ok(())
+}
+
+// Function to update the minting account (callable by the current minting account or canister controller)
+#[update]
+fn update_minting_account(new_minting_account: Account) -> Result<(), String> {
+    let new_minting_account = new_minting_account.canonical();
+    let _caller = ic_cdk::caller();
+    
+    // Allow the controller to update the minting account regardless of current setting
+    // This is needed for initial setup when minting account is anonymous
+    
+    // Update the minting account
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.minting_account = Some(new_minting_account);
+    });
+    
+    Ok(())
+}
+
+// Function to pause or resume approvals while transfers keep working (callable by a canister controller)
+#[update]
+fn set_approvals_paused(paused: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can pause approvals".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().approvals_paused = paused;
+    });
+
+    Ok(())
+}
+
+// Function to toggle rejection of transfers whose fee is at least the amount (callable by a canister controller)
+#[update]
+fn set_reject_fee_exceeding_amount(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can change the transfer guard".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().reject_fee_exceeding_amount = enabled;
+    });
+
+    Ok(())
+}
+
+// Function to limit how many funded subaccounts a principal may hold (callable by a canister controller)
+#[update]
+fn set_max_subaccounts_per_principal(limit: Option<u32>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the subaccount limit".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().max_subaccounts_per_principal = limit;
+    });
+
+    Ok(())
+}
+
+// Function to emit zero-amount approvals as "revoke" blocks (callable by a canister controller)
+// Applies to approvals recorded from now on; existing blocks keep their op
+#[update]
+fn set_tag_revocations(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can change revocation tagging".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().tag_revocations = enabled;
+    });
+
+    Ok(())
+}
+
+// Function to set or clear the minimum non-zero approval amount (callable by a canister controller)
+#[update]
+fn set_min_approval_amount(min_approval_amount: Option<Nat>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the minimum approval amount".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().min_approval_amount = min_approval_amount;
+    });
+
+    Ok(())
+}
+
+// Function to let an oracle canister steer the fee (callable by a canister controller)
+// The oracle must expose `get_fee : () -> (nat) query`; each poll moves the fee by at most max_fee_change_bps
+#[update]
+fn set_fee_oracle(fee_oracle: Option<Principal>, max_fee_change_bps: u16) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the fee oracle".to_string());
+    }
+
+    if max_fee_change_bps > 10_000 {
+        return Err("Fee change bound cannot exceed 10000 basis points".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.fee_oracle = fee_oracle;
+        data.max_fee_change_bps = max_fee_change_bps;
+    });
+
+    Ok(())
+}
+
+// Helper function to fetch the module hash after `delay` from a one-shot timer
+// Heap state is wiped on upgrade, so init and post_upgrade fetch the hash of each newly installed module
+fn start_module_hash_fetch(delay: Duration) {
+    ic_cdk_timers::set_timer(delay, || ic_cdk::spawn(fetch_module_hash()));
+}
+
+// Helper function to cache the module hash reported by the management canister's canister_info
+async fn fetch_module_hash() {
+    let request = CanisterInfoRequest {
+        canister_id: ic_cdk::id(),
+        num_requested_changes: None,
+    };
+    match canister_info(request).await {
+        Ok((info,)) => MODULE_HASH.with(|hash| *hash.borrow_mut() = info.module_hash),
+        Err(_) => start_module_hash_fetch(Duration::from_nanos(MODULE_HASH_RETRY_INTERVAL)),
+    }
+}
+
+// SHA-256 hash of the running wasm module for deploy verification (None until the fetch after install or upgrade completes)
+#[query]
+fn module_hash() -> Option<Vec<u8>> {
+    MODULE_HASH.with(|hash| hash.borrow().clone())
+}
+
+// Helper function to adopt the fee reported by the fee oracle (run every FEE_ORACLE_INTERVAL)
+async fn poll_fee_oracle() {
+    let oracle = match TOKEN_DATA.with(|data| data.borrow().fee_oracle) {
+        Some(oracle) => oracle,
+        None => return,
+    };
+
+    let result: Result<(Nat,), _> = ic_cdk::call(oracle, "get_fee", ()).await;
+    if let Ok((proposed,)) = result {
+        let new_fee = TOKEN_DATA.with(|data| {
+            let data = data.borrow();
+            // Ignore the answer if the oracle was replaced or removed while the call was in flight
+            (data.fee_oracle == Some(oracle)).then(|| data.bounded_fee(&proposed))
+        });
+        if let Some(new_fee) = new_fee {
+            change_fee(new_fee);
+        }
+    }
+}
+
+// Helper function to adopt a new transfer fee, recording a fee_change block when it differs from the current one
+fn change_fee(new_fee: Nat) {
+    let old_fee = TOKEN_DATA.with(|data| std::mem::replace(&mut data.borrow_mut().fee, new_fee.clone()));
+    if old_fee != new_fee {
+        record_transaction(Transaction::fee_change(FeeChange { old_fee, new_fee }, time()));
+    }
+}
+
+// Function to cap the blocks one icrc3_get_blocks call returns, or lift the cap with None
+// (callable by a canister controller)
+#[update]
+fn set_max_blocks_per_request(max_blocks_per_request: Option<u64>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the block request limit".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().max_blocks_per_request = max_blocks_per_request;
+    });
+
+    Ok(())
+}
+
+// Function to set the transfer fee (callable by a canister controller)
+// The change is recorded as a fee_change block so the history shows when each fee applied
+#[update]
+fn set_fee(new_fee: Nat) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the fee".to_string());
+    }
+
+    change_fee(new_fee);
+    Ok(())
+}
+
+// Function to sweep dust balances of opted-in accounts into one account (callable by a canister controller)
+#[update]
+fn set_dust_consolidation(consolidation_account: Option<Account>, threshold: Nat) -> Result<(), String> {
+    let consolidation_account = consolidation_account.map(Account::canonical);
+    if !caller_is_controller() {
+        return Err("Only a controller can configure dust consolidation".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        data.dust_consolidation_account = consolidation_account;
+        data.dust_threshold = threshold;
+    });
+
+    Ok(())
+}
+
+// Function for the caller to opt one of their accounts in or out of dust consolidation
+#[update]
+fn set_dust_consolidation_opt_in(subaccount: Option<Subaccount>, enabled: bool) {
+    let account = Account {
+        owner: ic_cdk::caller(),
+        subaccount,
+    }
+    .canonical();
+
+    DUST_OPT_INS.with(|opt_ins| {
+        let mut opt_ins = opt_ins.borrow_mut();
+        if enabled {
+            opt_ins.insert(account, time());
+        } else {
+            opt_ins.remove(&account);
+        }
+    });
+}
+
+// Time at which the account opted in to dust consolidation, if it did
+#[query]
+fn dust_consolidation_opt_in(account: Account) -> Option<u64> {
+    let account = account.canonical();
+    DUST_OPT_INS.with(|opt_ins| opt_ins.borrow().get(&account))
+}
+
+// Function to allow or disallow registering new aliases (callable by a canister controller)
+// Existing aliases keep resolving while disabled
+#[update]
+fn set_aliases_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can enable aliases".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().aliases_enabled = Some(enabled);
+    });
+
+    Ok(())
+}
+
+// Function to allow or disallow netted batch transfers (callable by a canister controller)
+#[update]
+fn set_batch_netting_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can enable batch netting".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().batch_netting_enabled = Some(enabled);
+    });
+
+    Ok(())
+}
+
+// Function to switch daily summary blocks on or off (callable by a canister controller)
+// Totals start from the next block, so the first summary after enabling may cover part of a day
+#[update]
+fn set_daily_summaries_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can enable daily summaries".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().daily_summaries_enabled = Some(enabled);
+    });
+
+    Ok(())
+}
+
+// Function to point `alias` at one of the caller's accounts
+// Fails if another principal holds the alias; the holder may re-point it to another of their accounts
+#[update]
+fn register_alias(alias: String, account: Account) -> Result<(), String> {
+    let account = account.canonical();
+    if !TOKEN_DATA.with(|data| data.borrow().aliases_enabled.unwrap_or(false)) {
+        return Err("Aliases are not enabled".to_string());
+    }
+
+    let caller = ic_cdk::caller();
+    if account.owner != caller {
+        return Err("Only the account owner can register an alias for it".to_string());
+    }
+
+    let alias = Alias::new(alias)?;
+    ALIASES.with(|aliases| {
+        let mut aliases = aliases.borrow_mut();
+        Alias::check_claim(aliases.get(&alias).as_ref(), caller)?;
+        aliases.insert(alias, account);
+        Ok(())
+    })
+}
+
+// Function to give up an alias so others can claim it (callable by the alias holder)
+#[update]
+fn release_alias(alias: String) -> Result<(), String> {
+    let alias = Alias(alias);
+    ALIASES.with(|aliases| {
+        let mut aliases = aliases.borrow_mut();
+        match aliases.get(&alias) {
+            Some(account) if account.owner == ic_cdk::caller() => {
+                aliases.remove(&alias);
+                Ok(())
+            }
+            Some(_) => Err("Only the alias holder can release it".to_string()),
+            None => Err("Alias is not registered".to_string()),
+        }
+    })
+}
+
+// Account an alias resolves to
+#[query]
+fn resolve_alias(alias: String) -> Option<Account> {
+    ALIASES.with(|aliases| aliases.borrow().get(&Alias(alias)))
+}
+
+// Transfer from one of the caller's accounts to the account `alias` resolves to
+#[update]
+fn transfer_to_alias(alias: String, amount: Nat, from_subaccount: Option<Vec<u8>>, memo: Option<Vec<u8>>) -> TransferResult {
+    let to = match resolve_alias(alias) {
+        Some(to) => to,
+        None => {
+            return TransferResult::Err(TransferError::GenericError {
+                error_code: ErrorCode::AliasNotFound.to_nat(),
+                message: "Alias is not registered".to_string(),
+            });
+        }
+    };
+
+    icrc1_transfer(TransferArgs {
+        from_subaccount,
+        to,
+        amount,
+        fee: None,
+        memo,
+        created_at_time: None,
+        deadline: None,
+        category: None,
+        on_behalf_of: None,
+        tip: None,
+    })
+}
+
+// Function to allow or disallow delegated transfers ledger-wide (callable by a canister controller)
+#[update]
+fn set_delegations_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can enable delegations".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().delegations_enabled = enabled;
+    });
+
+    Ok(())
+}
+
+// Function for the caller to let `delegate` transfer from one of their accounts up to `cap` (amount plus fees)
+// Unlike an ICRC-2 allowance the delegate transfers as the owner; a zero cap removes the delegation
+#[update]
+fn set_delegation(subaccount: Option<Subaccount>, delegate: Principal, cap: Nat, expires_at: Option<u64>) {
+    let owner = Account {
+        owner: ic_cdk::caller(),
+        subaccount,
+    }
+    .canonical();
+
+    DELEGATIONS.with(|delegations| {
+        let mut delegations = delegations.borrow_mut();
+        if cap.0.is_zero() {
+            delegations.remove(&delegation_key(&owner, delegate));
+        } else {
+            delegations.insert(delegation_key(&owner, delegate), Allowance {
+                allowance: cap,
+                expires_at,
+            });
+        }
+    });
+}
+
+// Remaining cap and expiry of a delegation from `owner` to `delegate`
+#[query]
+fn get_delegation(owner: Account, delegate: Principal) -> Option<Allowance> {
+    let owner = owner.canonical();
+    DELEGATIONS.with(|delegations| delegations.borrow().get(&delegation_key(&owner, delegate)))
+}
+
+// Function to set the per-transfer limit of each KYC tier, tier 0 first and in ascending order (callable by a canister controller)
+// An empty table turns KYC enforcement off
+#[update]
+fn set_kyc_tier_limits(limits: Vec<Nat>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set KYC tier limits".to_string());
+    }
+    validate_kyc_tier_limits(&limits)?;
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().kyc_tier_limits = limits;
+    });
+
+    Ok(())
+}
+
+// Function to assign an account's KYC tier, or reset it to tier 0 with None (callable by a canister controller)
+#[update]
+fn set_kyc_tier(account: Account, tier: Option<u8>) -> Result<(), String> {
+    let account = account.canonical();
+    if !caller_is_controller() {
+        return Err("Only a controller can set KYC tiers".to_string());
+    }
+
+    KYC_TIERS.with(|tiers| {
+        let mut tiers = tiers.borrow_mut();
+        match tier {
+            Some(tier) => tiers.insert(account, tier),
+            None => tiers.remove(&account),
+        };
+    });
+
+    Ok(())
+}
+
+// KYC tier of an account (0 when none was assigned)
+#[query]
+fn get_kyc_tier(account: Account) -> u8 {
+    let account = account.canonical();
+    KYC_TIERS.with(|tiers| tiers.borrow().get(&account).unwrap_or(0))
+}
+
+// Function to set the reputation below which accounts cannot send transfers, or disable the check with None
+// (callable by a canister controller)
+#[update]
+fn set_min_reputation(min_reputation: Option<i32>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the minimum reputation".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().min_reputation = min_reputation;
+    });
+
+    Ok(())
+}
+
+// Function to set an account's reputation, or reset it to 0 with None (callable by a canister controller)
+#[update]
+fn set_reputation(account: Account, score: Option<i32>) -> Result<(), String> {
+    let account = account.canonical();
+    if !caller_is_controller() {
+        return Err("Only a controller can set reputations".to_string());
+    }
+
+    REPUTATIONS.with(|reputations| {
+        let mut reputations = reputations.borrow_mut();
+        match score {
+            Some(score) => reputations.insert(account, Reputation(score)),
+            None => reputations.remove(&account),
+        };
+    });
+
+    Ok(())
+}
+
+// Reputation of an account (0 when none was set)
+#[query]
+fn get_reputation(account: Account) -> i32 {
+    let account = account.canonical();
+    REPUTATIONS.with(|reputations| reputations.borrow().get(&account).unwrap_or(Reputation(0)).0)
+}
+
+// Function to enable payment requests with the given lifetime, or disable them with None (callable by a canister controller)
+#[update]
+fn set_payment_request_ttl(ttl: Option<u64>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can configure payment requests".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().payment_request_ttl = ttl;
+    });
+
+    Ok(())
+}
+
+// Function to permanently disable minting, fixing the supply (callable by a canister controller)
+// There is deliberately no way to turn minting back on
+#[update]
+fn disable_minting() -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can disable minting".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().minting_disabled = true;
+    });
+
+    Ok(())
+}
+
+// Function to allow or disallow scheduling new transfers (callable by a canister controller)
+// Already scheduled transfers still execute or can be cancelled when disabled
+#[update]
+fn set_scheduled_transfers_enabled(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can enable scheduled transfers".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().scheduled_transfers_enabled = enabled;
+    });
+
+    Ok(())
+}
+
+// Function to stop recording blocks for approvals that do not change the allowance (callable by a canister controller)
+#[update]
+fn set_skip_unchanged_approvals(enabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can change approval logging".to_string());
     }
-    
-    // Check allowance
-    let allowance = ALLOWANCES.with(|allowances| {
-        allowances
-            .borrow()
-            .get(&AccountPair(from.clone(), spender.clone()))
-            .unwrap_or_else(|| Allowance {
-                allowance: Nat::from(0u64),
-                expires_at: None,
-            })
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().skip_unchanged_approvals = enabled;
     });
-    
-    // Check if the allowance has expired
-    if let Some(expires_at) = allowance.expires_at {
-        if expires_at < time() {
-            return TransferFromResult::Err(TransferFromError::InsufficientAllowance {
-                allowance: Nat::from(0u64),
-            });
-        }
+
+    Ok(())
+}
+
+// Function to charge a fee on every mint, paid out of the minted amount (callable by a canister controller)
+#[update]
+fn set_mint_fee(mint_fee: Option<Nat>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the mint fee".to_string());
     }
-    
-    // Check if the allowance is sufficient
-    if allowance.allowance < amount {
-        return TransferFromResult::Err(TransferFromError::InsufficientAllowance {
-            allowance: allowance.allowance,
-        });
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().mint_fee = mint_fee;
+    });
+
+    Ok(())
+}
+
+// Function to let icrc1_transfer fees accrue as debt up to a cap, or charge them per transfer again with None
+// (callable by a canister controller); debt already accrued stays payable through settle_fees
+#[update]
+fn set_fee_debt_cap(fee_debt_cap: Option<Nat>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the fee debt cap".to_string());
     }
-    
-    // Update balances
-    BALANCES.with(|balances| {
-        let mut balances = balances.borrow_mut();
-        
-        // Convert Nat to StableNat for storage
-        let stable_amount = StableNat::from_nat(amount.clone());
-        let total_deduction_clone = total_deduction.clone();
-        let from_balance_clone = from_balance.clone();
-        
-        // Deduct from sender
-        if from_balance_clone == total_deduction_clone {
-            // If exact amount, remove the entry
-            balances.remove(&from);
-        } else {
-            // Otherwise, update with new balance
-            let new_stable_balance = StableNat::from_nat(from_balance_clone - total_deduction_clone);
-            balances.insert(from.clone(), new_stable_balance);
-        }
-        
-        // Add to recipient
-        let stable_to_balance = balances.get(&to)
-            .unwrap_or_else(|| StableNat::from(0u64));
-        balances.insert(to.clone(), stable_to_balance + stable_amount);
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().fee_debt_cap = fee_debt_cap;
     });
-    
-    // Update allowance
-    let allowance_clone = allowance.allowance.clone();
-    let amount_clone = amount.clone();
-    let new_allowance = allowance_clone - amount_clone;
-    
-    ALLOWANCES.with(|allowances| {
-        let mut allowances = allowances.borrow_mut();
-        if new_allowance == Nat::from(0u64) {
-            allowances.remove(&AccountPair(from.clone(), spender.clone()));
-        } else {
-            allowances.insert(
-                AccountPair(from.clone(), spender.clone()),
-                Allowance {
-                    allowance: new_allowance,
-                    expires_at: allowance.expires_at,
-                },
-            );
-        }
+
+    Ok(())
+}
+
+// Function to set the account credited with collected fees, or burn fees with None
+// (callable by a canister controller)
+#[update]
+fn set_fee_collector(fee_collector: Option<Account>) -> Result<(), String> {
+    let fee_collector = fee_collector.map(Account::canonical);
+    if !caller_is_controller() {
+        return Err("Only a controller can set the fee collector".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().fee_collector = fee_collector;
     });
-    
-    // Record the transaction
-    let transfer = Transfer {
-        amount: amount.clone(),
-        from: from.clone(),
-        to: to.clone(),
-        spender: Some(spender.clone()),
-        memo,
-        fee: Some(fee.clone()),
-        created_at_time,
-    };
-    
-    let tx = Transaction::transfer(transfer, time());
-    let block_index = record_transaction(tx);
-    
-    TransferFromResult::Ok(block_index)
+
+    Ok(())
 }
 
-// ICRC-3 Get Blocks
-#[query]
-fn icrc3_get_blocks(args: GetBlocksArgs) -> GetBlocksResult {
-    let start = args.start.clone();
-    let length = args.length.clone();
-    
-    let mut blocks = Vec::new();
-    
-    TRANSACTIONS.with(|txs| {
-        let txs = txs.borrow();
-        let log_length = txs.len();
-        
-        // Convert transactions to blocks
-        for i in start.0.to_u64().unwrap_or(0)..std::cmp::min(
-            start.0.to_u64().unwrap_or(0) + length.0.to_u64().unwrap_or(0),
-            log_length as u64,
-        ) {
-            let stable_index = StableBlockIndex::new(i);
-            if let Some(tx) = txs.get(&stable_index) {
-                let block_value = transaction_to_value(&tx);
-                blocks.push(BlockWithId {
-                    id: Nat::from(i),
-                    block: block_value,
-                });
-            }
-        }
+// Function to set the account credited with transfer tips, or burn tips with None
+// (callable by a canister controller)
+#[update]
+fn set_tip_account(tip_account: Option<Account>) -> Result<(), String> {
+    let tip_account = tip_account.map(Account::canonical);
+    if !caller_is_controller() {
+        return Err("Only a controller can set the tip account".to_string());
+    }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().tip_account = tip_account;
     });
-    
-    let log_length = TRANSACTIONS.with(|txs| Nat::from(txs.borrow().len() as u64));
-    
-    GetBlocksResult {
-        log_length,
-        blocks,
-        archived_blocks: Vec::new(), // No archived blocks in this implementation
+
+    Ok(())
+}
+
+// Function to enable escrows with the given lifetime in nanoseconds, or disable new ones with None
+// (callable by a canister controller); open escrows keep their expiry
+#[update]
+fn set_escrow_ttl(escrow_ttl: Option<u64>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the escrow lifetime".to_string());
     }
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().escrow_ttl = escrow_ttl;
+    });
+
+    Ok(())
 }
 
-// Custom mint function (only callable by the minting account)
+// Function to set the lifetime in nanoseconds given to approvals that omit expires_at, or none with None
+// (callable by a canister controller); existing approvals are unaffected
 #[update]
-fn mint(to: Account, amount: Nat) -> TransferResult {
-    let caller = ic_cdk::caller();
-    let minting_account = TOKEN_DATA.with(|data| data.borrow().minting_account.clone());
-    
-    // Check if the caller is the minting account
-    if minting_account.is_none() || minting_account.as_ref().unwrap().owner != caller {
-        return TransferResult::Err(TransferError::GenericError {
-            error_code: Nat::from(1u64),
-            message: "Only the minting account can mint tokens".to_string(),
-        });
+fn set_default_approval_expiry(default_approval_expiry: Option<u64>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the default approval expiry".to_string());
     }
-    
-    // Convert Nat to StableNat for storage
-    let stable_amount = StableNat::from_nat(amount.clone());
-    
-    // Update the recipient's balance
-    BALANCES.with(|balances| {
-        let mut balances = balances.borrow_mut();
-        let stable_balance = balances.get(&to)
-            .unwrap_or_else(|| StableNat::from(0u64));
-        balances.insert(to.clone(), stable_balance + stable_amount);
+
+    TOKEN_DATA.with(|data| {
+        data.borrow_mut().default_approval_expiry = default_approval_expiry;
     });
-    
-    // Update total supply
-    let amount_clone = amount.clone();
+
+    Ok(())
+}
+
+// Function to require non-zero approvals to carry an expiry (callable by a canister controller)
+// Existing allowances without an expiry are left in place
+#[update]
+fn set_require_approval_expiry(required: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can require approval expiries".to_string());
+    }
+
     TOKEN_DATA.with(|data| {
-        let mut data = data.borrow_mut();
-        data.total_supply += amount_clone;
+        data.borrow_mut().require_approval_expiry = Some(required);
     });
-    
-    // Record the transaction
-    let mint = Mint {
-        amount: amount.clone(),
-        to: to.clone(),
-        memo: None,
-        created_at_time: Some(time()),
-    };
-    
-    let tx = Transaction::mint(mint, time());
-    let block_index = record_transaction(tx);
-    
-    TransferResult::Ok(block_index)
+
+    Ok(())
 }
 
-// Function to update the minting account (callable by the current minting account or canister controller)
+// Function to hold icrc1_transfer credits for a dispute window, or release them at once with None (callable by a canister controller)
+// Transfers already pending keep the window they were made with
 #[update]
-fn update_minting_account(new_minting_account: Account) -> Result<(), String> {
-    let _caller = ic_cdk::caller();
-    
-    // Allow the controller to update the minting account regardless of current setting
-    // This is needed for initial setup when minting account is anonymous
-    
-    // Update the minting account
+fn set_dispute_window(dispute_window: Option<u64>) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can set the dispute window".to_string());
+    }
+
     TOKEN_DATA.with(|data| {
-        let mut data = data.borrow_mut();
-        data.minting_account = Some(new_minting_account);
+        data.borrow_mut().dispute_window = dispute_window;
     });
-    
+
+    Ok(())
+}
+
+// Function to switch a single update method off or back on (callable by a canister controller)
+// Lets a faulty endpoint be disabled without redeploying; see DISABLEABLE_METHODS for the accepted names
+#[update]
+fn set_method_disabled(method: String, disabled: bool) -> Result<(), String> {
+    if !caller_is_controller() {
+        return Err("Only a controller can disable methods".to_string());
+    }
+
+    let method = MethodName::new(method)?;
+    DISABLED_METHODS.with(|methods| {
+        let mut methods = methods.borrow_mut();
+        if disabled {
+            methods.insert(method, ());
+        } else {
+            methods.remove(&method);
+        }
+    });
+
     Ok(())
 }
 
+// Update methods currently switched off by a controller
+#[query]
+fn disabled_methods() -> Vec<String> {
+    DISABLED_METHODS.with(|methods| methods.borrow().iter().map(|(method, _)| method.0).collect())
+}
+
+// Standards this ledger knows about with whether each is currently enabled
+// Paused approvals count as a disabled icrc2_approve
+#[query]
+fn standards_status() -> Vec<(String, bool)> {
+    let approvals_paused = TOKEN_DATA.with(|data| data.borrow().approvals_paused);
+    types::standards_status(|method| method_disabled(method) || (approvals_paused && method == "icrc2_approve"))
+}
+
+// Helper function to check whether a controller has switched off an update method
+fn method_disabled(method: &str) -> bool {
+    DISABLED_METHODS.with(|methods| methods.borrow().contains_key(&MethodName(method.to_string())))
+}
+
 // Custom burn function
 #[update]
 fn burn(from: Account, amount: Nat) -> TransferResult {
+    let from = from.canonical();
+    if method_disabled("burn") {
+        return TransferResult::Err(TransferError::TemporarilyUnavailable);
+    }
+
     let caller = ic_cdk::caller();
     
     // Check if the caller is authorized to burn tokens
     if from.owner != caller {
         return TransferResult::Err(TransferError::GenericError {
-            error_code: Nat::from(1u64),
+            error_code: ErrorCode::Unauthorized.to_nat(),
             message: "Only the account owner can burn their tokens".to_string(),
         });
     }
     
+    // Reject zero-amount burns
+    if let Err(message) = validate_amount(&amount) {
+        return TransferResult::Err(TransferError::GenericError {
+            error_code: ErrorCode::InvalidAmount.to_nat(),
+            message,
+        });
+    }
+    
+    // Reject dust burns below the configured minimum
+    if let Err(min_burn_amount) = TOKEN_DATA.with(|data| data.borrow().check_min_burn(&amount)) {
+        return TransferResult::Err(TransferError::BadBurn { min_burn_amount });
+    }
+    
     // Check if the account has enough tokens to burn
     let from_balance = get_account_balance(&from);
     if from_balance < amount {
         return TransferResult::Err(TransferError::InsufficientFunds { balance: from_balance });
     }
     
-    // Convert Nat to StableNat for storage
-    let _stable_amount = StableNat::from_nat(amount.clone());
+    // Update total supply first, so an inconsistent supply fails the burn instead of trapping
+    if let Err(err) = TOKEN_DATA.with(|data| data.borrow_mut().reduce_supply(&amount)) {
+        return TransferResult::Err(err);
+    }
     
     // Update the balance
     BALANCES.with(|balances| {
@@ -610,13 +3800,6 @@ fn burn(from: Account, amount: Nat) -> TransferResult {
         }
     });
     
-    // Update total supply
-    let amount_clone = amount.clone();
-    TOKEN_DATA.with(|data| {
-        let mut data = data.borrow_mut();
-        data.total_supply -= amount_clone;
-    });
-    
     // Record the transaction
     let burn = Burn {
         amount: amount.clone(),
@@ -638,6 +3821,12 @@ fn transaction_to_value(tx: &Transaction) -> Value {
     
     // Common fields
     map.push(("ts".to_string(), Value::Nat64(tx.timestamp)));
+    if let Some(phash) = &tx.phash {
+        map.push(("phash".to_string(), Value::Blob(phash.clone())));
+    }
+    if let Some(fee_collector) = &tx.fee_collector {
+        map.push(("fee_col".to_string(), account_to_value(fee_collector)));
+    }
     
     // Transaction-specific fields
     match tx.kind.as_str() {
@@ -647,6 +3836,10 @@ fn transaction_to_value(tx: &Transaction) -> Value {
                 map.push(("to".to_string(), account_to_value(&mint.to)));
                 map.push(("amt".to_string(), Value::Nat(mint.amount.clone())));
                 
+                if let Some(fee) = &mint.fee {
+                    map.push(("fee".to_string(), Value::Nat(fee.clone())));
+                }
+                
                 if let Some(memo) = &mint.memo {
                     map.push(("memo".to_string(), Value::Blob(memo.clone())));
                 }
@@ -678,18 +3871,29 @@ fn transaction_to_value(tx: &Transaction) -> Value {
                     map.push(("spender".to_string(), account_to_value(spender)));
                 }
                 
-                if let Some(fee) = &transfer.fee {
+                if let Some(fee) = tx.block_fee() {
                     map.push(("fee".to_string(), Value::Nat(fee.clone())));
                 }
                 
                 if let Some(memo) = &transfer.memo {
                     map.push(("memo".to_string(), Value::Blob(memo.clone())));
                 }
+                
+                if let Some(category) = &transfer.category {
+                    map.push(("category".to_string(), Value::Text(category.clone())));
+                }
+                
+                if let Some(tip) = &tx.tip {
+                    map.push(("tip".to_string(), Value::Nat(tip.amount.clone())));
+                    if let Some(tip_to) = &tip.to {
+                        map.push(("tip_to".to_string(), account_to_value(tip_to)));
+                    }
+                }
             }
         },
-        "approve" => {
+        "approve" | "revoke" => {
             if let Some(approve) = &tx.approve {
-                map.push(("op".to_string(), Value::Text("approve".to_string())));
+                map.push(("op".to_string(), Value::Text(tx.kind.clone())));
                 map.push(("from".to_string(), account_to_value(&approve.from)));
                 map.push(("spender".to_string(), account_to_value(&approve.spender)));
                 map.push(("amt".to_string(), Value::Nat(approve.amount.clone())));
@@ -711,6 +3915,48 @@ fn transaction_to_value(tx: &Transaction) -> Value {
                 }
             }
         },
+        "genesis" => {
+            if let Some(genesis) = &tx.genesis {
+                map.push(("btype".to_string(), Value::Text("genesis".to_string())));
+                map.push(("name".to_string(), Value::Text(genesis.name.clone())));
+                map.push(("symbol".to_string(), Value::Text(genesis.symbol.clone())));
+                map.push(("decimals".to_string(), Value::Nat(Nat::from(genesis.decimals))));
+                map.push(("fee".to_string(), Value::Nat(genesis.fee.clone())));
+            }
+        },
+        "fee" => {
+            if let Some(fee_payment) = &tx.fee_payment {
+                map.push(("op".to_string(), Value::Text("fee".to_string())));
+                map.push(("from".to_string(), account_to_value(&fee_payment.from)));
+                map.push(("amt".to_string(), Value::Nat(fee_payment.amount.clone())));
+            }
+        },
+        "daily_summary" => {
+            if let Some(summary) = &tx.daily_summary {
+                map.push(("btype".to_string(), Value::Text("daily_summary".to_string())));
+                map.push(("day".to_string(), Value::Nat64(summary.day_start)));
+                map.push(("minted".to_string(), Value::Nat(summary.minted.clone())));
+                map.push(("burned".to_string(), Value::Nat(summary.burned.clone())));
+                map.push(("transferred".to_string(), Value::Nat(summary.transferred.clone())));
+                map.push(("fees".to_string(), Value::Nat(summary.fees.clone())));
+            }
+        },
+        "fee_change" => {
+            if let Some(fee_change) = &tx.fee_change {
+                map.push(("op".to_string(), Value::Text("fee_change".to_string())));
+                map.push(("old_fee".to_string(), Value::Nat(fee_change.old_fee.clone())));
+                map.push(("fee".to_string(), Value::Nat(fee_change.new_fee.clone())));
+            }
+        },
+        "finalize" | "reverse" => {
+            if let Some(settlement) = &tx.settlement {
+                map.push(("op".to_string(), Value::Text(tx.kind.clone())));
+                map.push(("tx".to_string(), Value::Nat(settlement.transfer_block.clone())));
+                map.push(("from".to_string(), account_to_value(&settlement.from)));
+                map.push(("to".to_string(), account_to_value(&settlement.to)));
+                map.push(("amt".to_string(), Value::Nat(settlement.amount.clone())));
+            }
+        },
         _ => {}
     }
     
@@ -1,10 +1,14 @@
-use candid::{CandidType, Deserialize, Nat, Principal};
-use ic_stable_structures::{BoundedStorable, Storable};
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+use ic_stable_structures::{BoundedStorable, Memory, Storable};
 use std::borrow::Cow;
 use serde::Serialize;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
 use std::str::FromStr;
 use num_traits::cast::ToPrimitive;
+use num_traits::Zero;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
 
 // StableBlockIndex wrapper for u64 that implements BoundedStorable
 // This is used as a key for the TRANSACTIONS map
@@ -25,6 +29,11 @@ impl StableBlockIndex {
         let value = nat.0.to_u64().unwrap_or(0);
         Self(value)
     }
+
+    // Index for `nat`, or None if it is beyond any block index rather than aliasing block 0
+    pub fn try_from_nat(nat: &Nat) -> Option<Self> {
+        nat.0.to_u64().map(Self)
+    }
 }
 
 impl Storable for StableBlockIndex {
@@ -48,6 +57,9 @@ impl BoundedStorable for StableBlockIndex {
     const IS_FIXED_SIZE: bool = true;
 }
 
+// Magnitude bytes of the largest balance the ledger accepts
+pub const MAX_VALUE_BYTES: usize = 32;
+
 // StableNat wrapper for Nat that implements BoundedStorable
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct StableNat(pub Nat);
@@ -68,25 +80,42 @@ impl StableNat {
     pub fn as_nat(&self) -> &Nat {
         &self.0
     }
+    
+    // Largest value a balance may reach: 2^256 - 1, well inside MAX_SIZE and small enough that a block
+    // carrying several amounts at the cap still fits Transaction::MAX_SIZE
+    pub fn max_value() -> Nat {
+        Nat(BigUint::from_bytes_le(&[0xff; MAX_VALUE_BYTES]))
+    }
 }
 
 impl Storable for StableNat {
     fn to_bytes(&self) -> Cow<[u8]> {
-        // Convert Nat to bytes using its string representation
-        let bytes = self.0.0.to_string().into_bytes();
+        // Length byte followed by the little-endian magnitude
+        let magnitude = self.0.0.to_bytes_le();
+        let mut bytes = Vec::with_capacity(magnitude.len() + 1);
+        bytes.push(magnitude.len() as u8);
+        bytes.extend_from_slice(&magnitude);
         Cow::Owned(bytes)
     }
     
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        // Convert bytes back to Nat
-        let s = String::from_utf8(bytes.to_vec()).unwrap_or_default();
-        let nat = Nat::from_str(&s).unwrap_or_else(|_| Nat::from(0u64));
-        Self(nat)
+        match bytes.split_first() {
+            Some((&len, magnitude)) if len as usize == magnitude.len() => {
+                Self(Nat(BigUint::from_bytes_le(magnitude)))
+            }
+            // Balances written before the compact encoding are decimal strings; their first byte is an
+            // ASCII digit, which never equals the remaining length for any value below 10^48
+            _ => {
+                let s = String::from_utf8(bytes.to_vec()).unwrap_or_default();
+                Self(Nat::from_str(&s).unwrap_or_else(|_| Nat::from(0u64)))
+            }
+        }
     }
 }
 
 impl BoundedStorable for StableNat {
-    const MAX_SIZE: u32 = 100; // Maximum size in bytes for the Nat representation
+    // Fixed by the existing stable maps: one length byte plus up to 99 magnitude bytes
+    const MAX_SIZE: u32 = 100;
     const IS_FIXED_SIZE: bool = false;
 }
 
@@ -162,6 +191,20 @@ pub struct Account {
     pub subaccount: Option<Subaccount>,
 }
 
+impl Account {
+    // Per ICRC-1 an absent subaccount and the all-zero 32-byte subaccount name the same account
+    // Every account is canonicalized before it keys a map, so the all-zero default is always stored as None
+    pub fn canonical(self) -> Self {
+        match &self.subaccount {
+            Some(subaccount) if subaccount.len() == 32 && subaccount.iter().all(|byte| *byte == 0) => Self {
+                owner: self.owner,
+                subaccount: None,
+            },
+            _ => self,
+        }
+    }
+}
+
 impl ic_stable_structures::Storable for Account {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
         let mut bytes = Vec::new();
@@ -224,6 +267,229 @@ impl ic_stable_structures::BoundedStorable for Account {
     const IS_FIXED_SIZE: bool = false;
 }
 
+// Index of the histogram bucket a balance falls into, given sorted bucket boundaries
+// Bucket i covers [boundaries[i - 1], boundaries[i]); the last bucket is open-ended
+pub fn histogram_bucket(boundaries: &[Nat], balance: &Nat) -> usize {
+    boundaries.partition_point(|boundary| boundary <= balance)
+}
+
+// Index of the interval a timestamp falls into among the last `count` intervals ending at `now`
+// Index 0 is the oldest interval and count - 1 the most recent one
+pub fn interval_bucket(now: u64, interval_ns: u64, count: u32, timestamp: u64) -> Option<usize> {
+    if interval_ns == 0 || timestamp > now {
+        return None;
+    }
+    let age = (now - timestamp) / interval_ns;
+    if age >= count as u64 {
+        return None;
+    }
+    Some(count as usize - 1 - age as usize)
+}
+
+// Nanoseconds elapsed at `now` since the last recorded transaction (0 for an empty log)
+pub fn activity_gap(now: u64, last_timestamp: Option<u64>) -> u64 {
+    last_timestamp.map(|timestamp| now.saturating_sub(timestamp)).unwrap_or(0)
+}
+
+// (start, length) of the blocks within `radius` of `index`, clamped at the start of the log
+// Indices past the tip are simply absent when the range is read
+pub fn context_range(index: u64, radius: u32) -> (u64, u64) {
+    let start = index.saturating_sub(radius as u64);
+    let end = index.saturating_add(radius as u64).saturating_add(1);
+    (start, end - start)
+}
+
+// Totals for one account over a time window; `sent` excludes fees, which are reported separately
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub received: Nat,
+    pub sent: Nat,
+    pub fees_paid: Nat,
+    pub net_change: Int,
+}
+
+impl Statement {
+    pub fn for_account(account: &Account, txs: impl Iterator<Item = Transaction>) -> Self {
+        let mut received = Nat::from(0u64);
+        let mut debited = Nat::from(0u64);
+        let mut fees_paid = Nat::from(0u64);
+        for tx in txs {
+            let (credit, debit) = tx.balance_change(account);
+            received += credit;
+            debited += debit;
+            if tx.fee_payer().is_some_and(|payer| payer.clone().canonical() == account.clone().canonical()) {
+                fees_paid += tx.fee();
+            }
+        }
+
+        Self {
+            net_change: Int::from(received.clone()) - Int::from(debited.clone()),
+            sent: debited - fees_paid.clone(),
+            received,
+            fees_paid,
+        }
+    }
+}
+
+pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// (day start, minted, burned) for each of `days` days from `day_start`, bucketing transactions by timestamp
+// Transactions outside the window are ignored
+pub fn supply_by_day(txs: impl Iterator<Item = Transaction>, day_start: u64, days: u32) -> Vec<(u64, Nat, Nat)> {
+    let mut buckets: Vec<(u64, Nat, Nat)> = (0..days as u64)
+        .map(|day| (day_start.saturating_add(day.saturating_mul(NANOS_PER_DAY)), Nat::from(0u64), Nat::from(0u64)))
+        .collect();
+    for tx in txs {
+        let day = match tx.timestamp.checked_sub(day_start) {
+            Some(offset) => offset / NANOS_PER_DAY,
+            None => continue,
+        };
+        if let Some((_, minted, burned)) = buckets.get_mut(day as usize) {
+            let (tx_minted, tx_burned) = tx.supply_change();
+            *minted += tx_minted;
+            *burned += tx_burned;
+        }
+    }
+    buckets
+}
+
+// First index in [start, end) for which `pred` holds, assuming it holds for a suffix of the range
+// Returns `end` if it holds nowhere
+pub fn first_index_where(start: u64, end: u64, pred: impl Fn(u64) -> bool) -> u64 {
+    let (mut low, mut high) = (start, end);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    low
+}
+
+// (memory id, size in wasm pages) of every virtual memory that has been allocated
+// Id 255 is reserved by the memory manager
+pub fn memory_layout<M: Memory>(manager: &MemoryManager<M>) -> Vec<(u8, u64)> {
+    (0..u8::MAX)
+        .map(|id| (id, manager.get(MemoryId::new(id)).size()))
+        .filter(|(_, pages)| *pages > 0)
+        .collect()
+}
+
+// Index every account referenced by `tx` (from, to, spender, ...) under block `block`
+pub fn index_account_blocks<M: Memory>(
+    index: &mut ic_stable_structures::StableBTreeMap<AccountBlock, (), M>,
+    tx: &Transaction,
+    block: u64,
+) {
+    for account in tx.accounts() {
+        index.insert(AccountBlock(account, StableBlockIndex(block)), ());
+    }
+}
+
+// First and last block indices under which `account` is indexed, or None if it never appears
+pub fn account_block_bounds<M: Memory>(
+    index: &ic_stable_structures::StableBTreeMap<AccountBlock, (), M>,
+    account: &Account,
+) -> Option<(u64, u64)> {
+    let first = index
+        .range(AccountBlock(account.clone(), StableBlockIndex(0))..)
+        .next()
+        .filter(|(key, _)| &key.0 == account)
+        .map(|(key, _)| key.1)?;
+    let last = index
+        .iter_upper_bound(&AccountBlock(account.clone(), StableBlockIndex(u64::MAX)))
+        .next()
+        .filter(|(key, _)| &key.0 == account)
+        .map(|(key, _)| key.1)?;
+    Some((first.0, last.0))
+}
+
+// Up to `length` block indices touching `account`, in ascending order, starting at block `start`
+pub fn account_blocks<M: Memory>(
+    index: &ic_stable_structures::StableBTreeMap<AccountBlock, (), M>,
+    account: &Account,
+    start: u64,
+    length: usize,
+) -> Vec<u64> {
+    index
+        .range(AccountBlock(account.clone(), StableBlockIndex(start))..)
+        .take_while(|(key, _)| &key.0 == account)
+        .take(length)
+        .map(|(key, _)| key.1 .0)
+        .collect()
+}
+
+// Move every entry whose key is not in canonical form under its canonical key, merging it with an entry
+// already stored there via `merge(existing, moved)`
+pub fn merge_into_canonical_keys<K, V, M: Memory>(
+    map: &mut ic_stable_structures::StableBTreeMap<K, V, M>,
+    canonical: impl Fn(&K) -> K,
+    merge: impl Fn(V, V) -> V,
+) where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let moved: Vec<(K, V)> = map.iter().filter(|(key, _)| canonical(key) != *key).collect();
+    for (key, value) in moved {
+        map.remove(&key);
+        let key = canonical(&key);
+        let value = match map.remove(&key) {
+            Some(existing) => merge(existing, value),
+            None => value,
+        };
+        map.insert(key, value);
+    }
+}
+
+// Replace every value that `canonical` rewrites, leaving values already in canonical form untouched
+pub fn canonicalize_values<K, V, M: Memory>(
+    map: &mut ic_stable_structures::StableBTreeMap<K, V, M>,
+    canonical: impl Fn(&V) -> V,
+) where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable + PartialEq,
+{
+    let rewritten: Vec<(K, V)> = map
+        .iter()
+        .filter_map(|(key, value)| {
+            let canonical_value = canonical(&value);
+            (canonical_value != value).then_some((key, canonical_value))
+        })
+        .collect();
+    for (key, value) in rewritten {
+        map.insert(key, value);
+    }
+}
+
+// Block index of an identical request already accepted earlier in the same batch
+pub fn duplicate_in_batch<T: PartialEq>(accepted: &[(T, Nat)], request: &T) -> Option<Nat> {
+    accepted
+        .iter()
+        .find(|(earlier, _)| earlier == request)
+        .map(|(_, block_index)| block_index.clone())
+}
+
+// Sum of the `n` largest balances, keeping only the current top `n` in memory
+pub fn top_balances_sum(balances: impl Iterator<Item = Nat>, n: usize) -> Nat {
+    let mut top = std::collections::BinaryHeap::with_capacity(n + 1);
+    for balance in balances {
+        top.push(std::cmp::Reverse(balance));
+        if top.len() > n {
+            top.pop();
+        }
+    }
+    top.into_iter().fold(Nat::from(0u64), |sum, std::cmp::Reverse(balance)| sum + balance)
+}
+
+// The `n` entries with the oldest last-activity timestamp, oldest first
+pub fn most_dormant(mut entries: Vec<(Account, u64)>, n: usize) -> Vec<(Account, u64)> {
+    entries.sort_by_key(|(_, last_activity)| *last_activity);
+    entries.truncate(n);
+    entries
+}
+
 // Allowance Types
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Allowance {
@@ -292,6 +558,215 @@ impl ic_stable_structures::BoundedStorable for Allowance {
     const IS_FIXED_SIZE: bool = false;
 }
 
+impl Allowance {
+    // Whether this allowance (or delegation cap) has lapsed at `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map(|expires_at| expires_at < now).unwrap_or(false)
+    }
+
+    // The allowance as a spender can use it at `now`: an expired allowance reads as zero with no expiry
+    pub fn effective(self, now: u64) -> Allowance {
+        if self.is_expired(now) {
+            Allowance {
+                allowance: Nat::from(0u64),
+                expires_at: None,
+            }
+        } else {
+            self
+        }
+    }
+
+    // Whether this allowance (or delegation cap) is unexpired at `now` and covers `amount`
+    pub fn covers(&self, amount: &Nat, now: u64) -> bool {
+        !self.is_expired(now) && self.allowance >= *amount
+    }
+
+    // Amount after adding `delta` to, or subtracting it from, this allowance; decreases clamp at zero
+    pub fn adjusted(&self, delta: &Nat, increase: bool) -> Nat {
+        if increase {
+            self.allowance.clone() + delta.clone()
+        } else if *delta >= self.allowance {
+            Nat::from(0u64)
+        } else {
+            self.allowance.clone() - delta.clone()
+        }
+    }
+}
+
+// Tag of the ledger-owned subaccount holding transfers during the dispute window
+pub const DISPUTE_TAG: &[u8] = b"dispute";
+
+// Transfer whose amount is held in the dispute account until it is finalized or reversed
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingTransfer {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub final_at: u64,
+    // Payment request this transfer paid; reversing the transfer reopens it
+    pub payment_code: Option<PaymentCode>,
+}
+
+impl PendingTransfer {
+    // Whether the sender can still reverse the transfer at `now`
+    pub fn is_reversible(&self, now: u64) -> bool {
+        now < self.final_at
+    }
+}
+
+impl ic_stable_structures::Storable for PendingTransfer {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for PendingTransfer {
+    const MAX_SIZE: u32 = 512; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Tag of the ledger-owned subaccount holding the funds of every open escrow
+pub const ESCROW_TAG: &[u8] = b"escrow";
+
+// Account of the ledger's own principal under the subaccount named by `tag` (zero-padded to 32 bytes)
+// Funds held on behalf of users sit there, so every movement is an ordinary transfer block
+pub fn ledger_account(ledger: Principal, tag: &[u8]) -> Account {
+    let mut subaccount = vec![0u8; 32];
+    subaccount[..tag.len()].copy_from_slice(tag);
+    Account {
+        owner: ledger,
+        subaccount: Some(subaccount),
+    }
+}
+
+// Funds locked by the sender for the recipient until released, refunded or expired
+// Identified by the index of the transfer block that locked them
+pub type EscrowId = u64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Escrow {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub expires_at: u64,
+}
+
+impl Escrow {
+    // The sender releases the funds once the off-chain obligation is met
+    pub fn can_release(&self, caller: Principal) -> bool {
+        caller == self.from.owner
+    }
+
+    // The recipient may always hand the funds back; the sender may reclaim them once the escrow expires
+    pub fn can_refund(&self, caller: Principal, now: u64) -> bool {
+        caller == self.to.owner || (caller == self.from.owner && now >= self.expires_at)
+    }
+}
+
+impl ic_stable_structures::Storable for Escrow {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for Escrow {
+    const MAX_SIZE: u32 = 512; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// One-time payment request created by a merchant and paid by whoever presents its code
+pub type PaymentCode = u64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PaymentRequest {
+    pub merchant: Account,
+    pub amount: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub expires_at: u64,
+    pub paid_in: Option<Nat>,
+}
+
+impl PaymentRequest {
+    // Whether the request can still be paid at `now`; a paid request reports the block that consumed it
+    pub fn check_payable(&self, now: u64) -> Result<(), TransferError> {
+        if let Some(block_index) = &self.paid_in {
+            return Err(TransferError::Duplicate {
+                duplicate_of: block_index.clone(),
+            });
+        }
+        if now > self.expires_at {
+            return Err(TransferError::GenericError {
+                error_code: ErrorCode::PaymentCodeExpired.to_nat(),
+                message: "Payment code has expired".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl ic_stable_structures::Storable for PaymentRequest {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for PaymentRequest {
+    const MAX_SIZE: u32 = 512; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Transfer whose amount and fee are locked now and delivered at `execute_at`
+pub type ScheduleId = u64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledTransfer {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub execute_at: u64,
+}
+
+impl ScheduledTransfer {
+    // Amount held back from the sender until the transfer executes or is cancelled
+    pub fn locked_amount(&self) -> Nat {
+        self.amount.clone() + self.fee.clone()
+    }
+
+    // Whether the transfer should execute at `now`
+    pub fn is_due(&self, now: u64) -> bool {
+        now >= self.execute_at
+    }
+}
+
+impl ic_stable_structures::Storable for ScheduledTransfer {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for ScheduledTransfer {
+    const MAX_SIZE: u32 = 512; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
+}
+
 // Wrapper type for (Account, Account) to implement Storable and BoundedStorable
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AccountPair(pub Account, pub Account);
@@ -353,100 +828,724 @@ impl ic_stable_structures::BoundedStorable for AccountPair {
     const IS_FIXED_SIZE: bool = false;
 }
 
-// Transaction Types
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct Mint {
-    pub amount: Nat,
-    pub to: Account,
-    pub memo: Option<Vec<u8>>,
-    pub created_at_time: Option<u64>,
-}
+// Key of the per-account block index: ordered by account, then block, so an account's blocks are contiguous
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AccountBlock(pub Account, pub StableBlockIndex);
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct Burn {
-    pub amount: Nat,
-    pub from: Account,
-    pub spender: Option<Account>,
-    pub memo: Option<Vec<u8>>,
-    pub created_at_time: Option<u64>,
+impl ic_stable_structures::Storable for AccountBlock {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct Transfer {
-    pub amount: Nat,
-    pub from: Account,
-    pub to: Account,
-    pub spender: Option<Account>,
-    pub memo: Option<Vec<u8>>,
-    pub fee: Option<Nat>,
-    pub created_at_time: Option<u64>,
+impl ic_stable_structures::BoundedStorable for AccountBlock {
+    const MAX_SIZE: u32 = 256; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct Approve {
+// Identity of a transaction for deduplication within the transaction window
+// Ordered by created_at_time first so expired entries can be pruned from the front of the index
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DedupKey {
+    pub created_at_time: u64,
+    pub kind: String,
+    pub caller: Principal,
     pub from: Account,
-    pub spender: Account,
+    pub to: Account,
     pub amount: Nat,
-    pub expected_allowance: Option<Nat>,
-    pub expires_at: Option<u64>,
+    pub fee: Nat,
     pub memo: Option<Vec<u8>>,
-    pub fee: Option<Nat>,
-    pub created_at_time: Option<u64>,
-}
-
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct Transaction {
-    pub kind: String,
-    pub mint: Option<Mint>,
-    pub burn: Option<Burn>,
-    pub transfer: Option<Transfer>,
-    pub approve: Option<Approve>,
-    pub timestamp: u64,
 }
 
-impl Transaction {
-    pub fn burn(burn: Burn, timestamp: u64) -> Self {
+impl DedupKey {
+    // Key of a mint, which has no fee payer; `from` is the minting account
+    pub fn mint(created_at_time: u64, minting_account: &Account, to: &Account, amount: &Nat, memo: Option<Vec<u8>>) -> Self {
         Self {
-            kind: "burn".into(),
-            timestamp,
-            mint: None,
-            burn: Some(burn),
-            transfer: None,
-            approve: None,
+            created_at_time,
+            kind: "mint".to_string(),
+            caller: minting_account.owner,
+            from: minting_account.clone(),
+            to: to.clone(),
+            amount: amount.clone(),
+            fee: Nat::from(0u64),
+            memo,
         }
     }
 
-    pub fn mint(mint: Mint, timestamp: u64) -> Self {
-        Self {
-            kind: "mint".into(),
-            timestamp,
-            mint: Some(mint),
-            burn: None,
-            transfer: None,
-            approve: None,
-        }
+    // Whether the transaction can no longer be submitted, so its entry is no longer needed
+    pub fn is_expired(&self, now: u64, window: u64) -> bool {
+        now > self.created_at_time.saturating_add(window)
     }
+}
 
-    pub fn transfer(transfer: Transfer, timestamp: u64) -> Self {
-        Self {
-            kind: "transfer".into(),
-            timestamp,
-            mint: None,
-            burn: None,
-            transfer: Some(transfer),
-            approve: None,
-        }
+impl ic_stable_structures::Storable for DedupKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
     }
 
-    pub fn approve(approve: Approve, timestamp: u64) -> Self {
-        Self {
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for DedupKey {
+    const MAX_SIZE: u32 = 1024; // Maximum size in bytes
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Account label wrapper so labels can be stored as BoundedStorable values
+pub const MAX_LABEL_LENGTH: usize = 64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AccountLabel(pub String);
+
+impl AccountLabel {
+    pub fn new(label: String) -> Result<Self, String> {
+        if label.is_empty() {
+            return Err("Label must not be empty".to_string());
+        }
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(format!("Label must be at most {} bytes", MAX_LABEL_LENGTH));
+        }
+        Ok(Self(label))
+    }
+
+    // Only the owner of `account` may set or clear its label
+    pub fn check_owner(account: &Account, caller: Principal) -> Result<(), String> {
+        if account.owner != caller {
+            return Err("Only the account owner can label the account".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Storable for AccountLabel {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(String::from_utf8(bytes.to_vec()).unwrap_or_default())
+    }
+}
+
+impl BoundedStorable for AccountLabel {
+    const MAX_SIZE: u32 = MAX_LABEL_LENGTH as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Update methods that a controller can switch off individually
+pub const DISABLEABLE_METHODS: [&str; 5] = ["icrc1_transfer", "icrc2_approve", "icrc2_transfer_from", "mint", "burn"];
+
+// Name of a disabled update method, stored as a BoundedStorable key
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MethodName(pub String);
+
+impl MethodName {
+    pub fn new(method: String) -> Result<Self, String> {
+        if !DISABLEABLE_METHODS.contains(&method.as_str()) {
+            return Err(format!("Method must be one of {}", DISABLEABLE_METHODS.join(", ")));
+        }
+        Ok(Self(method))
+    }
+}
+
+impl Storable for MethodName {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(String::from_utf8(bytes.to_vec()).unwrap_or_default())
+    }
+}
+
+impl BoundedStorable for MethodName {
+    const MAX_SIZE: u32 = 32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Anti-fraud score of an account; accounts without a score have 0
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reputation(pub i32);
+
+impl Storable for Reputation {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut data = [0u8; 4];
+        data.copy_from_slice(&bytes[0..4]);
+        Self(i32::from_le_bytes(data))
+    }
+}
+
+impl BoundedStorable for Reputation {
+    const MAX_SIZE: u32 = 4;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Aliases are short human-friendly names such as "alice" or "coffee-shop"
+pub const MAX_ALIAS_LENGTH: usize = 32;
+
+// Unique name resolvable to an account, stored as a BoundedStorable key
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Alias(pub String);
+
+impl Alias {
+    pub fn new(alias: String) -> Result<Self, String> {
+        if alias.is_empty() || alias.len() > MAX_ALIAS_LENGTH {
+            return Err(format!("Alias must be between 1 and {} bytes", MAX_ALIAS_LENGTH));
+        }
+        if !alias.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+            return Err("Alias may only contain lowercase letters, digits, '-' and '_'".to_string());
+        }
+        Ok(Self(alias))
+    }
+
+    // An alias can be claimed if it is free, or re-pointed by the principal already holding it
+    pub fn check_claim(current: Option<&Account>, caller: Principal) -> Result<(), String> {
+        match current {
+            Some(account) if account.owner != caller => Err("Alias is already taken".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Storable for Alias {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(self.0.as_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(String::from_utf8(bytes.to_vec()).unwrap_or_default())
+    }
+}
+
+impl BoundedStorable for Alias {
+    const MAX_SIZE: u32 = MAX_ALIAS_LENGTH as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Transaction Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Mint {
+    pub amount: Nat,
+    pub to: Account,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+    pub fee: Option<Nat>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Burn {
+    pub amount: Nat,
+    pub from: Account,
+    pub spender: Option<Account>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub amount: Nat,
+    pub from: Account,
+    pub to: Account,
+    pub spender: Option<Account>,
+    pub memo: Option<Vec<u8>>,
+    pub fee: Option<Nat>,
+    pub created_at_time: Option<u64>,
+    pub category: Option<String>,
+}
+
+// Transaction categories are short lowercase tags such as "payroll" or "refund"
+pub const MAX_CATEGORY_LENGTH: usize = 32;
+
+pub fn validate_category(category: &str) -> Result<(), String> {
+    if category.is_empty() || category.len() > MAX_CATEGORY_LENGTH {
+        return Err(format!("Category must be 1 to {} characters", MAX_CATEGORY_LENGTH));
+    }
+    if !category
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err("Category may only contain a-z, 0-9, '_' and '-'".to_string());
+    }
+    Ok(())
+}
+
+// Memos are opaque bytes capped at the conventional ICRC-1 limit
+pub const MAX_MEMO_LENGTH: usize = 32;
+
+pub fn validate_memo(memo: Option<&[u8]>) -> Result<(), String> {
+    match memo {
+        Some(memo) if memo.len() > MAX_MEMO_LENGTH => {
+            Err(format!("Memo must be at most {} bytes", MAX_MEMO_LENGTH))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Transfers, mints and burns must move a positive amount
+pub fn validate_amount(amount: &Nat) -> Result<(), String> {
+    if amount.0.is_zero() {
+        return Err("amount must be positive".to_string());
+    }
+    Ok(())
+}
+
+// KYC tier limits must not decrease from one tier to the next, so that tier 0, the default for
+// unassigned accounts, is always the most restrictive
+pub fn validate_kyc_tier_limits(limits: &[Nat]) -> Result<(), String> {
+    if limits.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err("KYC tier limits must be in ascending order".to_string());
+    }
+    Ok(())
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Approve {
+    pub from: Account,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub fee: Option<Nat>,
+    pub created_at_time: Option<u64>,
+}
+
+impl Approve {
+    // Block op for this approval; approvals down to zero can be tagged as revocations
+    pub fn op(&self, tag_revocations: bool) -> &'static str {
+        if tag_revocations && self.amount.0.is_zero() {
+            "revoke"
+        } else {
+            "approve"
+        }
+    }
+}
+
+// Ledger description recorded as block 0 when a genesis block is emitted
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Genesis {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub fee: Nat,
+}
+
+// Outcome of a transfer held for the dispute window: finalized to the recipient or reversed to the sender
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Settlement {
+    pub transfer_block: Nat,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub reversed: bool,
+}
+
+// Payment of fees accrued by deferred-fee transfers
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeePayment {
+    pub from: Account,
+    pub amount: Nat,
+}
+
+// Voluntary extra amount paid by a transfer's sender, credited to the tip account or burned if `to` is None
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Tip {
+    pub amount: Nat,
+    pub to: Option<Account>,
+}
+
+// Change of the ledger's transfer fee, recorded so the block history shows when each fee applied
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeeChange {
+    pub old_fee: Nat,
+    pub new_fee: Nat,
+}
+
+// Totals of one day's blocks, recorded as a summary block once the next day's first block arrives
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DailySummary {
+    pub day_start: u64,
+    pub minted: Nat,
+    pub burned: Nat,
+    pub transferred: Nat,
+    pub fees: Nat,
+}
+
+impl DailySummary {
+    pub fn new(day_start: u64) -> Self {
+        Self {
+            day_start,
+            minted: Nat::from(0u64),
+            burned: Nat::from(0u64),
+            transferred: Nat::from(0u64),
+            fees: Nat::from(0u64),
+        }
+    }
+
+    pub fn add(&mut self, tx: &Transaction) {
+        if tx.mint.is_some() {
+            self.minted += tx.supply_change().0;
+        }
+        if let Some(burn) = &tx.burn {
+            self.burned += burn.amount.clone();
+        }
+        if let Some(transfer) = &tx.transfer {
+            self.transferred += transfer.amount.clone();
+        }
+        self.fees += tx.fee();
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    pub kind: String,
+    pub mint: Option<Mint>,
+    pub burn: Option<Burn>,
+    pub transfer: Option<Transfer>,
+    pub approve: Option<Approve>,
+    pub genesis: Option<Genesis>,
+    pub settlement: Option<Settlement>,
+    pub fee_payment: Option<FeePayment>,
+    pub tip: Option<Tip>,
+    // Account credited with this transaction's fee; None means the fee was burned
+    pub fee_collector: Option<Account>,
+    pub fee_change: Option<FeeChange>,
+    // Whether the caller left the fee for the ledger to fill in; such blocks omit it, as reference ledgers do
+    pub fee_implicit: Option<bool>,
+    pub daily_summary: Option<DailySummary>,
+    // Hash of the previous block's value; None only for the first block
+    pub phash: Option<Vec<u8>>,
+    pub timestamp: u64,
+}
+
+impl Transaction {
+    pub fn burn(burn: Burn, timestamp: u64) -> Self {
+        Self {
+            kind: "burn".into(),
+            timestamp,
+            mint: None,
+            burn: Some(burn),
+            transfer: None,
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn mint(mint: Mint, timestamp: u64) -> Self {
+        Self {
+            kind: "mint".into(),
+            timestamp,
+            mint: Some(mint),
+            burn: None,
+            transfer: None,
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn transfer(transfer: Transfer, timestamp: u64) -> Self {
+        Self {
+            kind: "transfer".into(),
+            timestamp,
+            mint: None,
+            burn: None,
+            transfer: Some(transfer),
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn genesis(genesis: Genesis, timestamp: u64) -> Self {
+        Self {
+            kind: "genesis".into(),
+            timestamp,
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            genesis: Some(genesis),
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn fee_payment(fee_payment: FeePayment, timestamp: u64) -> Self {
+        Self {
+            kind: "fee".into(),
+            timestamp,
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: Some(fee_payment),
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn fee_change(fee_change: FeeChange, timestamp: u64) -> Self {
+        Self {
+            kind: "fee_change".into(),
+            timestamp,
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: Some(fee_change),
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+
+    pub fn daily_summary(daily_summary: DailySummary, timestamp: u64) -> Self {
+        Self {
+            kind: "daily_summary".into(),
+            timestamp,
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: Some(daily_summary),
+            phash: None,
+        }
+    }
+
+    pub fn approve(approve: Approve, timestamp: u64) -> Self {
+        Self {
             kind: "approve".into(),
             timestamp,
             mint: None,
             burn: None,
             transfer: None,
             approve: Some(approve),
+            genesis: None,
+            settlement: None,
+            fee_payment: None,
+            tip: None,
+            fee_collector: None,
+            fee_change: None,
+            fee_implicit: None,
+            daily_summary: None,
+            phash: None,
+        }
+    }
+}
+
+impl Transaction {
+    // Amounts credited to and debited from `account` by this transaction, fees included
+    // Accounts are compared in canonical form, so blocks recorded with the all-zero subaccount still count
+    pub fn balance_change(&self, account: &Account) -> (Nat, Nat) {
+        let account = account.clone().canonical();
+        let is_account = |candidate: &Account| candidate.clone().canonical() == account;
+        let mut credit = Nat::from(0u64);
+        let mut debit = Nat::from(0u64);
+
+        if let Some(mint) = &self.mint {
+            if is_account(&mint.to) {
+                credit += mint.amount.clone();
+            }
+            // A mint fee recorded with a fee collector was minted to the collector on top of the amount
+            if let (Some(fee), true) = (&mint.fee, self.fee_collector.as_ref().is_some_and(is_account)) {
+                credit += fee.clone();
+            }
+        }
+        if let Some(burn) = &self.burn {
+            if is_account(&burn.from) {
+                debit += burn.amount.clone();
+            }
+        }
+        if let Some(transfer) = &self.transfer {
+            if is_account(&transfer.from) {
+                debit += transfer.amount.clone() + transfer.fee.clone().unwrap_or_else(|| Nat::from(0u64));
+            }
+            if is_account(&transfer.to) {
+                credit += transfer.amount.clone();
+            }
+        }
+        if let Some(approve) = &self.approve {
+            if is_account(&approve.from) {
+                debit += approve.fee.clone().unwrap_or_else(|| Nat::from(0u64));
+            }
+        }
+        // A reversal undoes the original transfer's credit; a finalization moves nothing new
+        if let Some(settlement) = &self.settlement {
+            if settlement.reversed {
+                if is_account(&settlement.from) {
+                    credit += settlement.amount.clone();
+                }
+                if is_account(&settlement.to) {
+                    debit += settlement.amount.clone();
+                }
+            }
+        }
+
+        if let Some(fee_payment) = &self.fee_payment {
+            if is_account(&fee_payment.from) {
+                debit += fee_payment.amount.clone();
+            }
         }
+        if self.fee_collector.as_ref().is_some_and(is_account) {
+            credit += self.fee();
+        }
+        // A tip is paid by the transfer's sender on top of the amount and fee
+        if let (Some(tip), Some(transfer)) = (&self.tip, &self.transfer) {
+            if is_account(&transfer.from) {
+                debit += tip.amount.clone();
+            }
+            if tip.to.as_ref().is_some_and(is_account) {
+                credit += tip.amount.clone();
+            }
+        }
+
+        (credit, debit)
+    }
+
+    // (amount minted, amount burned) by this transaction
+    pub fn supply_change(&self) -> (Nat, Nat) {
+        let mut minted = self.mint.as_ref().map(|mint| mint.amount.clone()).unwrap_or_else(|| Nat::from(0u64));
+        if let (Some(Mint { fee: Some(fee), .. }), Some(_)) = (&self.mint, &self.fee_collector) {
+            minted += fee.clone();
+        }
+        let mut burned = self.burn.as_ref().map(|burn| burn.amount.clone()).unwrap_or_else(|| Nat::from(0u64));
+        if let Some(Tip { amount, to: None }) = &self.tip {
+            burned += amount.clone();
+        }
+        (minted, burned)
+    }
+
+    // Signed change in the balance of `account` caused by this transaction
+    pub fn balance_delta(&self, account: &Account) -> Int {
+        let (credit, debit) = self.balance_change(account);
+        Int::from(credit) - Int::from(debit)
+    }
+
+    // Fee charged by this transaction (transfer or approve fee, or a settled fee debt)
+    pub fn fee(&self) -> Nat {
+        self.transfer.as_ref().and_then(|transfer| transfer.fee.clone())
+            .or_else(|| self.approve.as_ref().and_then(|approve| approve.fee.clone()))
+            .or_else(|| self.fee_payment.as_ref().map(|fee_payment| fee_payment.amount.clone()))
+            .unwrap_or_else(|| Nat::from(0u64))
+    }
+
+    // Transfer fee recorded in the block: the charged fee unless the caller left it implicit
+    pub fn block_fee(&self) -> Option<&Nat> {
+        if self.fee_implicit == Some(true) {
+            return None;
+        }
+        self.transfer.as_ref().and_then(|transfer| transfer.fee.as_ref())
+    }
+
+    // Account charged this transaction's fee, if it carries one
+    pub fn fee_payer(&self) -> Option<&Account> {
+        self.transfer.as_ref().map(|transfer| &transfer.from)
+            .or_else(|| self.approve.as_ref().map(|approve| &approve.from))
+            .or_else(|| self.fee_payment.as_ref().map(|fee_payment| &fee_payment.from))
+    }
+
+    // Amount moved by a transfer, mint or burn
+    pub fn amount(&self) -> Option<&Nat> {
+        self.transfer.as_ref().map(|transfer| &transfer.amount)
+            .or_else(|| self.mint.as_ref().map(|mint| &mint.amount))
+            .or_else(|| self.burn.as_ref().map(|burn| &burn.amount))
+    }
+
+    // Memo attached to this transaction, if any
+    pub fn memo(&self) -> Option<&Vec<u8>> {
+        self.mint.as_ref().and_then(|mint| mint.memo.as_ref())
+            .or_else(|| self.burn.as_ref().and_then(|burn| burn.memo.as_ref()))
+            .or_else(|| self.transfer.as_ref().and_then(|transfer| transfer.memo.as_ref()))
+            .or_else(|| self.approve.as_ref().and_then(|approve| approve.memo.as_ref()))
+    }
+
+    // Every account taking part in this transaction as sender, recipient or spender, in canonical form
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts = Vec::new();
+        if let Some(mint) = &self.mint {
+            accounts.push(mint.to.clone());
+        }
+        if let Some(burn) = &self.burn {
+            accounts.push(burn.from.clone());
+        }
+        if let Some(transfer) = &self.transfer {
+            accounts.push(transfer.from.clone());
+            accounts.push(transfer.to.clone());
+            accounts.extend(transfer.spender.clone());
+        }
+        if let Some(approve) = &self.approve {
+            accounts.push(approve.from.clone());
+            accounts.push(approve.spender.clone());
+        }
+        if let Some(settlement) = &self.settlement {
+            accounts.push(settlement.from.clone());
+            accounts.push(settlement.to.clone());
+        }
+        if let Some(fee_payment) = &self.fee_payment {
+            accounts.push(fee_payment.from.clone());
+        }
+        if let Some(tip) = &self.tip {
+            accounts.extend(tip.to.clone());
+        }
+        accounts.extend(self.fee_collector.clone());
+        accounts.into_iter().map(Account::canonical).collect()
     }
 }
 
@@ -468,7 +1567,7 @@ impl ic_stable_structures::BoundedStorable for Transaction {
 }
 
 // Token Data
-#[derive(Clone, Debug)]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct TokenData {
     pub name: String,
     pub symbol: String,
@@ -477,6 +1576,577 @@ pub struct TokenData {
     pub total_supply: Nat,
     pub minting_account: Option<Account>,
     pub next_block_index: Nat,
+    pub approvals_paused: bool,
+    pub reject_fee_exceeding_amount: bool,
+    pub treasury_account: Option<Account>,
+    pub treasury_bps: u16,
+    pub max_subaccounts_per_principal: Option<u32>,
+    pub tag_revocations: bool,
+    pub min_approval_amount: Option<Nat>,
+    pub fee_oracle: Option<Principal>,
+    pub max_fee_change_bps: u16,
+    pub dust_consolidation_account: Option<Account>,
+    pub dust_threshold: Nat,
+    pub delegations_enabled: bool,
+    pub kyc_tier_limits: Vec<Nat>,
+    pub payment_request_ttl: Option<u64>,
+    pub next_payment_code: u64,
+    pub minting_disabled: bool,
+    pub scheduled_transfers_enabled: bool,
+    pub next_schedule_id: u64,
+    pub skip_unchanged_approvals: bool,
+    pub mint_fee: Option<Nat>,
+    pub dispute_window: Option<u64>,
+    // opt so that token data saved before this field existed still decodes after an upgrade
+    pub require_approval_expiry: Option<bool>,
+    // Fees of icrc1_transfer accrue as per-account debt up to this cap instead of being charged per transfer
+    pub fee_debt_cap: Option<Nat>,
+    // Hash of the latest block, carried as phash by the next one
+    pub last_block_hash: Option<Vec<u8>>,
+    // Lifetime of new escrows in nanoseconds; None disables create_escrow
+    pub escrow_ttl: Option<u64>,
+    // Account credited with transfer tips; None burns them
+    pub tip_account: Option<Account>,
+    // Whether owners may register aliases for their accounts; None on ledgers created before aliases
+    pub aliases_enabled: Option<bool>,
+    // Account credited with collected fees; None burns them from the total supply
+    pub fee_collector: Option<Account>,
+    // Lifetime in nanoseconds given to approvals that omit expires_at; None leaves them unbounded
+    pub default_approval_expiry: Option<u64>,
+    // Reputation below which an account cannot send transfers; None disables the check
+    pub min_reputation: Option<i32>,
+    // Most blocks one icrc3_get_blocks call returns, local and archived combined; None leaves it unbounded
+    pub max_blocks_per_request: Option<u64>,
+    // (block index, amount) of the largest transfer, mint or burn recorded so far
+    pub largest_transaction: Option<(Nat, Nat)>,
+    // Whether net_batch_transfer may be used; None on ledgers created before netting
+    pub batch_netting_enabled: Option<bool>,
+    // Total supply mints may not push past; None leaves minting uncapped
+    pub max_supply: Option<Nat>,
+    // Whether a daily_summary block closes each day; None on ledgers created before summaries
+    pub daily_summaries_enabled: Option<bool>,
+    // Running totals of the day in progress while summaries are enabled
+    pub daily_totals: Option<DailySummary>,
+    // Smallest amount that may be burned, directly or by transferring to the minting account; None means zero
+    pub min_burn_amount: Option<Nat>,
+    // Whether entries stored under the all-zero subaccount were merged into the default account; None on
+    // ledgers created before accounts were canonicalized
+    pub default_subaccounts_merged: Option<bool>,
+    // Whether blocks recorded before the per-account index existed have been indexed; None on older ledgers
+    pub account_blocks_backfilled: Option<bool>,
+}
+
+// TokenData is saved to stable memory across upgrades using candid
+impl Storable for TokenData {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+// Nets a batch of (from, to, amount) transfers pairwise: opposite transfers between the same two accounts
+// offset each other, leaving a single transfer of the difference in the larger direction
+// Returns (position of an entry sent in the net direction, net amount) per pair, in order of each pair's
+// first entry; pairs that cancel exactly are left out
+pub fn net_transfers(transfers: &[(Account, Account, Nat)]) -> Vec<(usize, Nat)> {
+    struct Edge {
+        forward: Option<usize>,
+        backward: Option<usize>,
+        forward_amount: Nat,
+        backward_amount: Nat,
+    }
+
+    let mut slots: std::collections::BTreeMap<(Account, Account), usize> = std::collections::BTreeMap::new();
+    let mut edges: Vec<Edge> = Vec::new();
+    for (position, (from, to, amount)) in transfers.iter().enumerate() {
+        let forward = from <= to;
+        let key = if forward { (from.clone(), to.clone()) } else { (to.clone(), from.clone()) };
+        let slot = *slots.entry(key).or_insert_with(|| {
+            edges.push(Edge {
+                forward: None,
+                backward: None,
+                forward_amount: Nat::from(0u64),
+                backward_amount: Nat::from(0u64),
+            });
+            edges.len() - 1
+        });
+        let edge = &mut edges[slot];
+        if forward {
+            edge.forward.get_or_insert(position);
+            edge.forward_amount += amount.clone();
+        } else {
+            edge.backward.get_or_insert(position);
+            edge.backward_amount += amount.clone();
+        }
+    }
+
+    edges
+        .into_iter()
+        .filter_map(|edge| {
+            if edge.forward_amount > edge.backward_amount {
+                Some((edge.forward?, edge.forward_amount - edge.backward_amount))
+            } else if edge.backward_amount > edge.forward_amount {
+                Some((edge.backward?, edge.backward_amount - edge.forward_amount))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Standards implemented by this ledger
+pub const SUPPORTED_STANDARDS: [&str; 3] = ["ICRC-1", "ICRC-2", "ICRC-3"];
+
+// Standards reported by standards_status with the update methods each depends on; ICRC-10 and
+// ICRC-21 are listed so clients can see they are not available here
+pub const STANDARD_METHODS: [(&str, &[&str]); 5] = [
+    ("ICRC-1", &["icrc1_transfer"]),
+    ("ICRC-2", &["icrc2_approve", "icrc2_transfer_from"]),
+    ("ICRC-3", &[]),
+    ("ICRC-10", &[]),
+    ("ICRC-21", &[]),
+];
+
+// Each standard with whether it is currently usable: supported and none of its methods disabled
+pub fn standards_status(method_disabled: impl Fn(&str) -> bool) -> Vec<(String, bool)> {
+    STANDARD_METHODS
+        .iter()
+        .map(|(standard, methods)| {
+            let enabled = SUPPORTED_STANDARDS.contains(standard) && !methods.iter().any(|method| method_disabled(method));
+            (standard.to_string(), enabled)
+        })
+        .collect()
+}
+
+impl TokenData {
+    // Index and hash of the last block, or None while the log is empty
+    pub fn tip(&self) -> Option<(u64, Vec<u8>)> {
+        let last_block_index = self.next_block_index.0.to_u64()?.checked_sub(1)?;
+        Some((last_block_index, self.last_block_hash.clone()?))
+    }
+
+    // Whether minting `amount` could leave a balance too large to store; no balance exceeds the supply
+    pub fn mint_exceeds_max_balance(&self, amount: &Nat) -> bool {
+        self.total_supply.clone() + amount.clone() > StableNat::max_value()
+    }
+
+    // Whether minting `amount` would push the total supply past max_supply, if one is set
+    pub fn exceeds_max_supply(&self, amount: &Nat) -> bool {
+        self.max_supply
+            .as_ref()
+            .is_some_and(|max_supply| self.total_supply.clone() + amount.clone() > *max_supply)
+    }
+
+    // Add `tx` to the running totals of its day, returning the previous day's totals if it starts a new day
+    pub fn track_daily_totals(&mut self, tx: &Transaction) -> Option<DailySummary> {
+        if !self.daily_summaries_enabled.unwrap_or(false) {
+            self.daily_totals = None;
+            return None;
+        }
+        let day_start = tx.timestamp - tx.timestamp % NANOS_PER_DAY;
+        let finished = match &self.daily_totals {
+            Some(totals) if totals.day_start != day_start => self.daily_totals.take(),
+            _ => None,
+        };
+        self.daily_totals.get_or_insert_with(|| DailySummary::new(day_start)).add(tx);
+        finished
+    }
+
+    pub fn min_burn_amount(&self) -> Nat {
+        self.min_burn_amount.clone().unwrap_or_else(|| Nat::from(0u64))
+    }
+
+    // Err with the minimum if `amount` is too small to burn
+    pub fn check_min_burn(&self, amount: &Nat) -> Result<(), Nat> {
+        let min_burn_amount = self.min_burn_amount();
+        if *amount < min_burn_amount {
+            return Err(min_burn_amount);
+        }
+        Ok(())
+    }
+
+    // Like check_min_burn, applied to a transfer to `to` when `to` is the minting account
+    pub fn check_min_burn_to(&self, to: &Account, amount: &Nat) -> Result<(), Nat> {
+        if self.minting_account.as_ref() != Some(to) {
+            return Ok(());
+        }
+        self.check_min_burn(amount)
+    }
+
+    // Remember the block at `index` if it moves more than the largest transaction so far
+    pub fn track_largest_transaction(&mut self, index: &Nat, tx: &Transaction) {
+        if let Some(amount) = tx.amount() {
+            if self.largest_transaction.as_ref().is_none_or(|(_, largest)| amount > largest) {
+                self.largest_transaction = Some((index.clone(), amount.clone()));
+            }
+        }
+    }
+
+    // Total fees charged for a batch of `count` transfers (the fee is flat per transfer)
+    pub fn batch_fee(&self, count: u32) -> Nat {
+        self.fee.clone() * Nat::from(count)
+    }
+
+    // Whether a transfer of `amount` is rejected because the fee would eat all of it
+    pub fn fee_exceeds_amount(&self, amount: &Nat) -> bool {
+        self.reject_fee_exceeding_amount && self.fee >= *amount
+    }
+
+    // Portion of a mint allocated to the treasury (treasury_bps / 10000 of the amount)
+    pub fn treasury_share(&self, amount: &Nat) -> Nat {
+        if self.treasury_account.is_none() {
+            return Nat::from(0u64);
+        }
+        amount.clone() * Nat::from(self.treasury_bps) / Nat::from(10_000u64)
+    }
+
+    // How a mint of `amount` is split: (credited to the recipient, credited to the treasury)
+    // The mint fee comes off the top and goes to the treasury with its share of the rest;
+    // without a treasury account the fee is left to mint_fee_collector
+    pub fn mint_split(&self, amount: &Nat) -> (Nat, Nat) {
+        let fee = self.mint_fee.clone().unwrap_or_else(|| Nat::from(0u64));
+        let net = if *amount > fee { amount.clone() - fee.clone() } else { Nat::from(0u64) };
+        let share = self.treasury_share(&net);
+        let treasury = if self.treasury_account.is_some() { share.clone() + fee } else { Nat::from(0u64) };
+        (net - share, treasury)
+    }
+
+    // Account credited with the mint fee when there is no treasury account: the fee collector, if any
+    // With neither, the fee is simply not minted
+    pub fn mint_fee_collector(&self) -> Option<Account> {
+        match (&self.treasury_account, &self.mint_fee) {
+            (None, Some(fee)) if !fee.0.is_zero() => self.fee_collector.clone(),
+            _ => None,
+        }
+    }
+
+    // Whether a principal already holding `existing` funded subaccounts may fund another one
+    pub fn allows_new_subaccount(&self, existing: u32) -> bool {
+        self.max_subaccounts_per_principal
+            .map(|limit| existing < limit)
+            .unwrap_or(true)
+    }
+
+    // Whether a non-zero approval is below the configured minimum (zero is always allowed for revocation)
+    pub fn approval_below_minimum(&self, amount: &Nat) -> bool {
+        match &self.min_approval_amount {
+            Some(minimum) => !amount.0.is_zero() && amount < minimum,
+            None => false,
+        }
+    }
+
+    // Remove `amount` from the total supply, failing instead of underflowing if it exceeds the supply
+    pub fn reduce_supply(&mut self, amount: &Nat) -> Result<(), TransferError> {
+        if *amount > self.total_supply {
+            return Err(TransferError::GenericError {
+                error_code: ErrorCode::SupplyUnderflow.to_nat(),
+                message: "Burn amount exceeds the total supply".to_string(),
+            });
+        }
+        self.total_supply -= amount.clone();
+        Ok(())
+    }
+
+    // Fee debt an account would owe after deferring `fee`, or None when fees are charged per transfer
+    // Fails once the debt would exceed fee_debt_cap
+    pub fn accrue_fee_debt(&self, debt: &Nat, fee: &Nat) -> Result<Option<Nat>, TransferError> {
+        let cap = match &self.fee_debt_cap {
+            Some(cap) => cap,
+            None => return Ok(None),
+        };
+        let debt = debt.clone() + fee.clone();
+        if &debt > cap {
+            return Err(TransferError::GenericError {
+                error_code: ErrorCode::FeeDebtLimitReached.to_nat(),
+                message: "Accrued fee debt would exceed the cap; call settle_fees first".to_string(),
+            });
+        }
+        Ok(Some(debt))
+    }
+
+    // Expiry to store for an approval: the one requested, else `now` plus default_approval_expiry if configured
+    pub fn approval_expiry(&self, requested: Option<u64>, now: u64) -> Option<u64> {
+        requested.or_else(|| self.default_approval_expiry.map(|default| now.saturating_add(default)))
+    }
+
+    // Whether transfers from an account with reputation `score` are blocked by min_reputation
+    pub fn below_min_reputation(&self, score: Reputation) -> bool {
+        self.min_reputation.map(|min| score.0 < min).unwrap_or(false)
+    }
+
+    // Whether a non-zero approval without an expiry must be rejected (zero is always allowed for revocation)
+    pub fn approval_missing_expiry(&self, amount: &Nat, expires_at: Option<u64>) -> bool {
+        self.require_approval_expiry.unwrap_or(false) && !amount.0.is_zero() && expires_at.is_none()
+    }
+
+    // Fee to adopt when the fee oracle reports `proposed`, moving at most max_fee_change_bps / 10000
+    // of the current fee per update, rounded up and never less than 1 so small and zero fees can still move
+    pub fn bounded_fee(&self, proposed: &Nat) -> Nat {
+        let max_change = if self.max_fee_change_bps == 0 {
+            Nat::from(0u64)
+        } else {
+            let scaled = self.fee.clone() * Nat::from(self.max_fee_change_bps) + Nat::from(9_999u64);
+            (scaled / Nat::from(10_000u64)).max(Nat::from(1u64))
+        };
+        let ceiling = self.fee.clone() + max_change.clone();
+        let floor = if max_change > self.fee { Nat::from(0u64) } else { self.fee.clone() - max_change };
+        if *proposed > ceiling {
+            ceiling
+        } else if *proposed < floor {
+            floor
+        } else {
+            proposed.clone()
+        }
+    }
+
+    // Account an opted-in `account` holding `balance` should be swept into, if that balance is dust
+    pub fn dust_consolidation_target(&self, account: &Account, balance: &Nat) -> Option<Account> {
+        let target = self.dust_consolidation_account.as_ref()?;
+        if target == account || balance.0.is_zero() || *balance >= self.dust_threshold {
+            return None;
+        }
+        Some(target.clone())
+    }
+
+    // Largest amount an account of KYC `tier` may send in one transfer (None when tiers are not enforced)
+    // Tiers beyond the table get the limit of the highest configured tier
+    pub fn kyc_limit(&self, tier: u8) -> Option<Nat> {
+        self.kyc_tier_limits
+            .get(tier as usize)
+            .or_else(|| self.kyc_tier_limits.last())
+            .cloned()
+    }
+
+    // Rewrite every configured account in canonical form
+    pub fn canonicalize_accounts(&mut self) {
+        for account in [
+            &mut self.minting_account,
+            &mut self.treasury_account,
+            &mut self.dust_consolidation_account,
+            &mut self.tip_account,
+            &mut self.fee_collector,
+        ] {
+            *account = account.take().map(Account::canonical);
+        }
+    }
+
+    // Override the built-in defaults with whatever the deployer supplied
+    pub fn apply_init_args(&mut self, args: &InitArgs) {
+        if let Some(name) = &args.name {
+            self.name = name.clone();
+        }
+        if let Some(symbol) = &args.symbol {
+            self.symbol = symbol.clone();
+        }
+        if let Some(decimals) = args.decimals {
+            self.decimals = decimals;
+        }
+        if let Some(fee) = &args.fee {
+            self.fee = fee.clone();
+        }
+        if let Some(minting_account) = &args.minting_account {
+            self.minting_account = Some(minting_account.clone().canonical());
+        }
+    }
+
+    // Description of the ledger recorded in the genesis block
+    pub fn genesis(&self) -> Genesis {
+        Genesis {
+            name: self.name.clone(),
+            symbol: self.symbol.clone(),
+            decimals: self.decimals,
+            fee: self.fee.clone(),
+        }
+    }
+
+    // Read-only snapshot of the current settings
+    pub fn config(&self) -> TokenConfig {
+        TokenConfig {
+            name: self.name.clone(),
+            symbol: self.symbol.clone(),
+            decimals: self.decimals,
+            fee: self.fee.clone(),
+            minting_account: self.minting_account.clone(),
+            approvals_paused: self.approvals_paused,
+            reject_fee_exceeding_amount: self.reject_fee_exceeding_amount,
+            treasury_account: self.treasury_account.clone(),
+            treasury_bps: self.treasury_bps,
+            max_subaccounts_per_principal: self.max_subaccounts_per_principal,
+            tag_revocations: self.tag_revocations,
+            min_approval_amount: self.min_approval_amount.clone(),
+            fee_oracle: self.fee_oracle,
+            max_fee_change_bps: self.max_fee_change_bps,
+            dust_consolidation_account: self.dust_consolidation_account.clone(),
+            dust_threshold: self.dust_threshold.clone(),
+            delegations_enabled: self.delegations_enabled,
+            kyc_tier_limits: self.kyc_tier_limits.clone(),
+            payment_request_ttl: self.payment_request_ttl,
+            minting_disabled: self.minting_disabled,
+            scheduled_transfers_enabled: self.scheduled_transfers_enabled,
+            skip_unchanged_approvals: self.skip_unchanged_approvals,
+            mint_fee: self.mint_fee.clone(),
+            dispute_window: self.dispute_window,
+            require_approval_expiry: self.require_approval_expiry.unwrap_or(false),
+            fee_debt_cap: self.fee_debt_cap.clone(),
+            escrow_ttl: self.escrow_ttl,
+            tip_account: self.tip_account.clone(),
+            aliases_enabled: self.aliases_enabled.unwrap_or(false),
+            fee_collector: self.fee_collector.clone(),
+            default_approval_expiry: self.default_approval_expiry,
+            min_reputation: self.min_reputation,
+            max_blocks_per_request: self.max_blocks_per_request,
+            batch_netting_enabled: self.batch_netting_enabled.unwrap_or(false),
+            max_supply: self.max_supply.clone(),
+            daily_summaries_enabled: self.daily_summaries_enabled.unwrap_or(false),
+            min_burn_amount: self.min_burn_amount(),
+            supported_standards: SUPPORTED_STANDARDS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+// Canister Init Arguments
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitArgs {
+    pub emit_genesis_block: Option<bool>,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub fee: Option<Nat>,
+    pub minting_account: Option<Account>,
+}
+
+// Token Configuration Snapshot
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenConfig {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub fee: Nat,
+    pub minting_account: Option<Account>,
+    pub approvals_paused: bool,
+    pub reject_fee_exceeding_amount: bool,
+    pub treasury_account: Option<Account>,
+    pub treasury_bps: u16,
+    pub max_subaccounts_per_principal: Option<u32>,
+    pub tag_revocations: bool,
+    pub min_approval_amount: Option<Nat>,
+    pub fee_oracle: Option<Principal>,
+    pub max_fee_change_bps: u16,
+    pub dust_consolidation_account: Option<Account>,
+    pub dust_threshold: Nat,
+    pub delegations_enabled: bool,
+    pub kyc_tier_limits: Vec<Nat>,
+    pub payment_request_ttl: Option<u64>,
+    pub minting_disabled: bool,
+    pub scheduled_transfers_enabled: bool,
+    pub skip_unchanged_approvals: bool,
+    pub mint_fee: Option<Nat>,
+    pub dispute_window: Option<u64>,
+    pub require_approval_expiry: bool,
+    pub fee_debt_cap: Option<Nat>,
+    pub escrow_ttl: Option<u64>,
+    pub tip_account: Option<Account>,
+    pub aliases_enabled: bool,
+    pub fee_collector: Option<Account>,
+    pub default_approval_expiry: Option<u64>,
+    pub min_reputation: Option<i32>,
+    pub max_blocks_per_request: Option<u64>,
+    pub batch_netting_enabled: bool,
+    pub max_supply: Option<Nat>,
+    pub daily_summaries_enabled: bool,
+    pub min_burn_amount: Nat,
+    pub supported_standards: Vec<String>,
+}
+
+// Error Codes
+// Codes reported through GenericError; the named error variants share the same
+// numbering so clients can decide whether a failed call is worth retrying
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Unauthorized = 1,
+    FeeExceedsAmount = 2,
+    DeadlinePassed = 3,
+    SubaccountLimitReached = 4,
+    InvalidCategory = 5,
+    ApprovalBelowMinimum = 6,
+    DelegationExceeded = 7,
+    KycLimitExceeded = 8,
+    BatchTooLarge = 9,
+    PaymentCodeNotFound = 10,
+    PaymentCodeExpired = 11,
+    MintingDisabled = 12,
+    InvalidMemo = 13,
+    ApprovalExpiryRequired = 14,
+    FeeDebtLimitReached = 15,
+    NoFeeDebt = 16,
+    InvalidAmount = 17,
+    SupplyUnderflow = 18,
+    AliasNotFound = 19,
+    ReputationTooLow = 20,
+    BalanceOverflow = 21,
+    MaxSupplyExceeded = 22,
+    BadFee = 100,
+    BadBurn = 101,
+    InsufficientFunds = 102,
+    InsufficientAllowance = 103,
+    AllowanceChanged = 104,
+    Expired = 105,
+    TooOld = 106,
+    CreatedInFuture = 107,
+    Duplicate = 108,
+    TemporarilyUnavailable = 109,
+}
+
+impl ErrorCode {
+    pub fn from_code(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(Self::Unauthorized),
+            2 => Some(Self::FeeExceedsAmount),
+            3 => Some(Self::DeadlinePassed),
+            4 => Some(Self::SubaccountLimitReached),
+            5 => Some(Self::InvalidCategory),
+            6 => Some(Self::ApprovalBelowMinimum),
+            7 => Some(Self::DelegationExceeded),
+            8 => Some(Self::KycLimitExceeded),
+            9 => Some(Self::BatchTooLarge),
+            10 => Some(Self::PaymentCodeNotFound),
+            11 => Some(Self::PaymentCodeExpired),
+            12 => Some(Self::MintingDisabled),
+            13 => Some(Self::InvalidMemo),
+            14 => Some(Self::ApprovalExpiryRequired),
+            15 => Some(Self::FeeDebtLimitReached),
+            16 => Some(Self::NoFeeDebt),
+            17 => Some(Self::InvalidAmount),
+            18 => Some(Self::SupplyUnderflow),
+            19 => Some(Self::AliasNotFound),
+            20 => Some(Self::ReputationTooLow),
+            21 => Some(Self::BalanceOverflow),
+            22 => Some(Self::MaxSupplyExceeded),
+            100 => Some(Self::BadFee),
+            101 => Some(Self::BadBurn),
+            102 => Some(Self::InsufficientFunds),
+            103 => Some(Self::InsufficientAllowance),
+            104 => Some(Self::AllowanceChanged),
+            105 => Some(Self::Expired),
+            106 => Some(Self::TooOld),
+            107 => Some(Self::CreatedInFuture),
+            108 => Some(Self::Duplicate),
+            109 => Some(Self::TemporarilyUnavailable),
+            _ => None,
+        }
+    }
+
+    pub fn to_nat(self) -> Nat {
+        Nat::from(self as u64)
+    }
+
+    // Only transient conditions are retriable; everything else needs the caller to change the request
+    pub fn is_retriable(self) -> bool {
+        matches!(self, Self::TemporarilyUnavailable | Self::CreatedInFuture)
+    }
 }
 
 // ICRC-1 Transfer Types
@@ -488,6 +2158,18 @@ pub struct TransferArgs {
     pub fee: Option<Nat>,
     pub memo: Option<Vec<u8>>,
     pub created_at_time: Option<u64>,
+    pub deadline: Option<u64>,
+    pub category: Option<String>,
+    pub on_behalf_of: Option<Principal>,
+    // Voluntary extra amount for the tip account (burned if none is set)
+    pub tip: Option<Nat>,
+}
+
+impl TransferArgs {
+    // Whether the transfer arrived after its client-supplied deadline
+    pub fn is_past_deadline(&self, now: u64) -> bool {
+        self.deadline.map(|deadline| now > deadline).unwrap_or(false)
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -504,6 +2186,17 @@ pub enum TransferError {
 
 pub type TransferResult = Result<Nat, TransferError>;
 
+// Outcome of a transfer with the balances it left behind, saving clients a follow-up query
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DetailedTransfer {
+    pub block_index: Nat,
+    pub from_balance_after: Nat,
+    pub to_balance_after: Nat,
+    pub fee_charged: Nat,
+}
+
+pub type DetailedTransferResult = Result<DetailedTransfer, TransferError>;
+
 // ICRC-2 Approve Types
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct ApproveArgs {
@@ -593,6 +2286,109 @@ pub struct GetBlocksResult {
     pub archived_blocks: Vec<ArchivedBlocks>,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetArchivesArgs {
+    // List only archives after this canister id
+    pub from: Option<Principal>,
+}
+
+// Canister holding blocks [start, end]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+// (start, length) of a contiguous run of blocks
+pub type BlockRange = (u64, u64);
+
+// Most blocks one icrc3_get_blocks call returns whatever the request, keeping replies under the message size limit
+pub const MAX_GET_BLOCKS_LENGTH: u64 = 2_000;
+
+// Blocks one icrc3_get_blocks call may return: the configured max_blocks_per_request, never above MAX_GET_BLOCKS_LENGTH
+pub fn get_blocks_cap(max_blocks_per_request: Option<u64>) -> u64 {
+    max_blocks_per_request.map_or(MAX_GET_BLOCKS_LENGTH, |max| max.min(MAX_GET_BLOCKS_LENGTH))
+}
+
+// Split a get_blocks request for [start, start + length) into the (start, length) ranges served by archives
+// (below `first_local`) and by the ledger itself, clipped to the log and sharing one budget of `cap` blocks
+pub fn split_block_request(
+    start: u64,
+    length: u64,
+    first_local: u64,
+    log_length: u64,
+    cap: Option<u64>,
+) -> (Option<BlockRange>, Option<BlockRange>) {
+    let end = start.saturating_add(std::cmp::min(length, cap.unwrap_or(u64::MAX))).min(log_length);
+    let archived_end = std::cmp::min(first_local, end);
+    let archived = (start < archived_end).then(|| (start, archived_end - start));
+    let local_start = std::cmp::max(start, first_local);
+    let local = (local_start < end).then(|| (local_start, end - local_start));
+    (archived, local)
+}
+
+// Archive list for a ledger that still stores all its blocks in [first_block, next_block_index) itself
+// The ledger describes its own range so clients have a discovery point before archiving exists
+pub fn ledger_archives(ledger: Principal, first_block: &Nat, next_block_index: &Nat, from: Option<Principal>) -> Vec<ArchiveInfo> {
+    if first_block >= next_block_index || from.is_some_and(|from| from >= ledger) {
+        return Vec::new();
+    }
+    vec![ArchiveInfo {
+        canister_id: ledger,
+        start: first_block.clone(),
+        end: next_block_index.clone() - 1u64,
+    }]
+}
+
+// Block schema a ledger produces, identified by the `op` value in the block map
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+const ICRC3_URL: &str = "https://github.com/dfinity/ICRC-1/blob/main/standards/ICRC-3/README.md";
+const ICRC2_URL: &str = "https://github.com/dfinity/ICRC-1/blob/main/standards/ICRC-2/README.md";
+const LEDGER_URL: &str = "https://github.com/shreyadwivedee/defi_ai_agent_consultant";
+
+// Every block type recorded by transaction_to_value, standard ones first
+// Ledger-specific blocks only appear once their features are enabled; finalize and reverse are only found in
+// logs written before held transfers moved through the dispute account
+pub fn supported_block_types() -> Vec<BlockType> {
+    [
+        ("mint", ICRC3_URL),
+        ("burn", ICRC3_URL),
+        ("xfer", ICRC3_URL),
+        ("approve", ICRC2_URL),
+        ("revoke", LEDGER_URL),
+        ("finalize", LEDGER_URL),
+        ("reverse", LEDGER_URL),
+        ("fee", LEDGER_URL),
+        ("fee_change", LEDGER_URL),
+        ("genesis", LEDGER_URL),
+        ("daily_summary", LEDGER_URL),
+    ]
+        .iter()
+        .map(|(block_type, url)| BlockType {
+            block_type: block_type.to_string(),
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+// Blocks plus the log version they were read at; `blocks` is None when the caller's known tip is current
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetBlocksVersioned {
+    pub tip: Vec<u8>,
+    pub blocks: Option<Vec<BlockWithId>>,
+}
+
+// Opaque version tag of the block log; it changes whenever a block is appended
+pub fn log_tip_tag(next_block_index: &Nat) -> Vec<u8> {
+    next_block_index.0.to_bytes_be()
+}
+
 // Value Types for ICRC-3
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum Value {
@@ -605,6 +2401,321 @@ pub enum Value {
     Map(Vec<(String, Value)>),
 }
 
+// Block fields that hold an account encoded as [owner blob, optional subaccount blob]
+const ACCOUNT_FIELDS: [&str; 5] = ["from", "to", "spender", "tip_to", "fee_col"];
+
+impl Value {
+    // JSON rendering for off-chain indexers: numbers become decimal strings so no precision is lost,
+    // blobs become hex, and account fields become {"owner": principal text, "subaccount": hex}
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::Blob(bytes) => format!("\"{}\"", hex_string(bytes)),
+            Value::Text(text) => json_string(text),
+            Value::Nat(nat) => format!("\"{}\"", nat.0),
+            Value::Nat64(nat64) => format!("\"{}\"", nat64),
+            Value::Int(int) => format!("\"{}\"", int.0),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_json).collect();
+                format!("[{}]", values.join(","))
+            }
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let rendered = match value {
+                            Value::Array(parts) if ACCOUNT_FIELDS.contains(&key.as_str()) => account_json(parts),
+                            _ => None,
+                        };
+                        format!("{}:{}", json_string(key), rendered.unwrap_or_else(|| value.to_json()))
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(","))
+            }
+        }
+    }
+}
+
+// {"owner": principal text, "subaccount": hex or null} for an account-encoded array
+fn account_json(parts: &[Value]) -> Option<String> {
+    let owner = match parts.first() {
+        Some(Value::Blob(owner)) => Principal::try_from_slice(owner).ok()?,
+        _ => return None,
+    };
+    let subaccount = match parts.get(1) {
+        Some(Value::Blob(subaccount)) => format!("\"{}\"", hex_string(subaccount)),
+        None => "null".to_string(),
+        _ => return None,
+    };
+    Some(format!("{{\"owner\":\"{}\",\"subaccount\":{}}}", owner, subaccount))
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// JSON string literal with quotes, backslashes and control characters escaped
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// First byte of a memo holding structured key-values, telling it apart from opaque bytes
+pub const STRUCTURED_MEMO_MARKER: u8 = 0xCB;
+
+// Canonical memo bytes for a list of key-values: the marker, then the entries sorted by key
+// Lengths and counts are single bytes and numbers are LEB128, so the result must fit MAX_MEMO_LENGTH
+pub fn encode_structured_memo(entries: &[(String, Value)]) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![STRUCTURED_MEMO_MARKER];
+    encode_memo_map(entries, &mut bytes)?;
+    if bytes.len() > MAX_MEMO_LENGTH {
+        return Err(format!("Structured memo encodes to {} bytes, at most {} allowed", bytes.len(), MAX_MEMO_LENGTH));
+    }
+    Ok(bytes)
+}
+
+// Key-values of a structured memo, or None for opaque or non-canonical memo bytes
+pub fn decode_structured_memo(memo: &[u8]) -> Option<Vec<(String, Value)>> {
+    let mut bytes = memo.strip_prefix(&[STRUCTURED_MEMO_MARKER])?;
+    let entries = decode_memo_map(&mut bytes)?;
+    bytes.is_empty().then_some(entries)
+}
+
+fn encode_memo_len(len: usize, bytes: &mut Vec<u8>) -> Result<(), String> {
+    let len = u8::try_from(len).map_err(|_| "Structured memo is too large".to_string())?;
+    bytes.push(len);
+    Ok(())
+}
+
+fn encode_memo_map(entries: &[(String, Value)], bytes: &mut Vec<u8>) -> Result<(), String> {
+    let mut sorted: Vec<&(String, Value)> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    if sorted.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err("Structured memo keys must be unique".to_string());
+    }
+    encode_memo_len(sorted.len(), bytes)?;
+    for (key, value) in sorted {
+        encode_memo_len(key.len(), bytes)?;
+        bytes.extend_from_slice(key.as_bytes());
+        encode_memo_value(value, bytes)?;
+    }
+    Ok(())
+}
+
+fn encode_memo_value(value: &Value, bytes: &mut Vec<u8>) -> Result<(), String> {
+    match value {
+        Value::Blob(blob) => {
+            bytes.push(0);
+            encode_memo_len(blob.len(), bytes)?;
+            bytes.extend_from_slice(blob);
+        }
+        Value::Text(text) => {
+            bytes.push(1);
+            encode_memo_len(text.len(), bytes)?;
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        Value::Nat(nat) => {
+            bytes.push(2);
+            nat.encode(&mut *bytes).expect("writing to a vector cannot fail");
+        }
+        Value::Nat64(nat64) => {
+            bytes.push(3);
+            Nat::from(*nat64).encode(&mut *bytes).expect("writing to a vector cannot fail");
+        }
+        Value::Int(int) => {
+            bytes.push(4);
+            int.encode(&mut *bytes).expect("writing to a vector cannot fail");
+        }
+        Value::Array(values) => {
+            bytes.push(5);
+            encode_memo_len(values.len(), bytes)?;
+            for value in values {
+                encode_memo_value(value, bytes)?;
+            }
+        }
+        Value::Map(entries) => {
+            bytes.push(6);
+            encode_memo_map(entries, bytes)?;
+        }
+    }
+    Ok(())
+}
+
+fn decode_memo_bytes<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (&len, rest) = bytes.split_first()?;
+    if rest.len() < len as usize {
+        return None;
+    }
+    let (data, rest) = rest.split_at(len as usize);
+    *bytes = rest;
+    Some(data)
+}
+
+fn decode_memo_map(bytes: &mut &[u8]) -> Option<Vec<(String, Value)>> {
+    let (&count, rest) = bytes.split_first()?;
+    *bytes = rest;
+    let mut entries: Vec<(String, Value)> = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let key = String::from_utf8(decode_memo_bytes(bytes)?.to_vec()).ok()?;
+        // Keys must be strictly increasing, so every map has exactly one encoding
+        if entries.last().is_some_and(|(previous, _)| *previous >= key) {
+            return None;
+        }
+        let value = decode_memo_value(bytes)?;
+        entries.push((key, value));
+    }
+    Some(entries)
+}
+
+fn decode_memo_value(bytes: &mut &[u8]) -> Option<Value> {
+    let (&tag, rest) = bytes.split_first()?;
+    *bytes = rest;
+    match tag {
+        0 => Some(Value::Blob(decode_memo_bytes(bytes)?.to_vec())),
+        1 => Some(Value::Text(String::from_utf8(decode_memo_bytes(bytes)?.to_vec()).ok()?)),
+        2 => Nat::decode(bytes).ok().map(Value::Nat),
+        3 => Nat::decode(bytes).ok()?.0.to_u64().map(Value::Nat64),
+        4 => Int::decode(bytes).ok().map(Value::Int),
+        5 => {
+            let (&count, rest) = bytes.split_first()?;
+            *bytes = rest;
+            (0..count).map(|_| decode_memo_value(bytes)).collect::<Option<Vec<_>>>().map(Value::Array)
+        }
+        6 => decode_memo_map(bytes).map(Value::Map),
+        _ => None,
+    }
+}
+
+// Representation-independent hash of a value following the ICRC-3 hashing rules:
+// numbers hash their LEB128 encoding, blobs and text their bytes, arrays the concatenation of their
+// element hashes, and maps the sorted concatenation of (key hash, value hash) pairs
+pub fn hash_value(value: &Value) -> [u8; 32] {
+    match value {
+        Value::Nat(nat) => {
+            let mut bytes = Vec::new();
+            nat.encode(&mut bytes).expect("writing to a vector cannot fail");
+            Sha256::digest(bytes).into()
+        }
+        Value::Nat64(nat64) => hash_value(&Value::Nat(Nat::from(*nat64))),
+        Value::Int(int) => {
+            let mut bytes = Vec::new();
+            int.encode(&mut bytes).expect("writing to a vector cannot fail");
+            Sha256::digest(bytes).into()
+        }
+        Value::Blob(bytes) => Sha256::digest(bytes).into(),
+        Value::Text(text) => Sha256::digest(text.as_bytes()).into(),
+        Value::Array(values) => {
+            let mut hasher = Sha256::new();
+            for value in values {
+                hasher.update(hash_value(value));
+            }
+            hasher.finalize().into()
+        }
+        Value::Map(entries) => {
+            let mut pairs: Vec<[u8; 64]> = entries
+                .iter()
+                .map(|(key, value)| {
+                    let mut pair = [0u8; 64];
+                    pair[..32].copy_from_slice(&Sha256::digest(key.as_bytes()));
+                    pair[32..].copy_from_slice(&hash_value(value));
+                    pair
+                })
+                .collect();
+            pairs.sort();
+            let mut hasher = Sha256::new();
+            for pair in pairs {
+                hasher.update(pair);
+            }
+            hasher.finalize().into()
+        }
+    }
+}
+
+// Whether consecutive blocks are linked by their phash and the last one hashes to `tip`
+// An empty slice is consistent only with no tip
+pub fn verify_chain_tail(blocks: &[Value], tip: Option<&[u8]>) -> bool {
+    let linked = blocks.windows(2).all(|pair| {
+        let phash = match &pair[1] {
+            Value::Map(entries) => entries.iter().find(|(key, _)| key == "phash").map(|(_, value)| value),
+            _ => None,
+        };
+        phash == Some(&Value::Blob(hash_value(&pair[0]).to_vec()))
+    });
+    let tip_matches = match blocks.last() {
+        Some(last) => tip == Some(&hash_value(last)[..]),
+        None => tip.is_none(),
+    };
+    linked && tip_matches
+}
+
+// Certificate of the block log tip for icrc3_get_tip_certificate
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DataCertificate {
+    // IC certificate over the canister's certified data
+    pub certificate: Vec<u8>,
+    // CBOR hash tree whose root is the certified data
+    pub hash_tree: Vec<u8>,
+}
+
+// Labeled leaves of the ICRC-3 tip tree in label order; the index is LEB128 encoded
+fn tip_tree_leaves(last_block_index: u64, last_block_hash: &[u8]) -> [(&'static [u8], Vec<u8>); 2] {
+    let mut index = Vec::new();
+    Nat::from(last_block_index).encode(&mut index).expect("writing to a vector cannot fail");
+    [(b"last_block_hash", last_block_hash.to_vec()), (b"last_block_index", index)]
+}
+
+fn hash_tree_node(domain: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+// Root hash of the tip tree fork(labeled("last_block_hash", leaf), labeled("last_block_index", leaf)),
+// which the ledger sets as its certified data after every block
+pub fn tip_tree_root(last_block_index: u64, last_block_hash: &[u8]) -> [u8; 32] {
+    let [left, right] = tip_tree_leaves(last_block_index, last_block_hash).map(|(label, value)| {
+        let leaf = hash_tree_node("ic-hashtree-leaf", &[&value]);
+        hash_tree_node("ic-hashtree-labeled", &[label, &leaf])
+    });
+    hash_tree_node("ic-hashtree-fork", &[&left, &right])
+}
+
+// The tip tree in the CBOR form clients verify against the certificate, with the self-describing tag
+// Nodes are arrays tagged 1 (fork), 2 (labeled) and 3 (leaf)
+pub fn encode_tip_tree(last_block_index: u64, last_block_hash: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0xd9, 0xd9, 0xf7, 0x83, 0x01];
+    for (label, value) in tip_tree_leaves(last_block_index, last_block_hash) {
+        bytes.extend([0x83, 0x02]);
+        encode_cbor_bytes(label, &mut bytes);
+        bytes.extend([0x82, 0x03]);
+        encode_cbor_bytes(&value, &mut bytes);
+    }
+    bytes
+}
+
+// CBOR byte string header and data; tree labels and values are well under 256 bytes
+fn encode_cbor_bytes(data: &[u8], bytes: &mut Vec<u8>) {
+    if data.len() < 24 {
+        bytes.push(0x40 | data.len() as u8);
+    } else {
+        bytes.extend([0x58, data.len() as u8]);
+    }
+    bytes.extend_from_slice(data);
+}
+
 // QueryArchiveFn for ICRC-3
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct QueryArchiveFn<Input: CandidType, Output: CandidType> {
@@ -1,13 +1,66 @@
 // Mock tests for ICRC-3 token backend
 // These tests simulate the behavior without requiring the PocketIC binary
 
-use candid::{Nat, Principal};
+use candid::{Int, Nat, Principal};
+use ic_stable_structures::Storable;
 
 // Import types from the backend
 #[path = "../src/types.rs"]
 mod types;
 use types::*;
 
+// Token data matching the canister's defaults
+fn default_token_data() -> TokenData {
+    TokenData {
+        name: "ICRC3 Token".to_string(),
+        symbol: "ICR3".to_string(),
+        decimals: 8,
+        fee: Nat::from(10_000),
+        total_supply: Nat::from(0),
+        minting_account: None,
+        next_block_index: Nat::from(0),
+        approvals_paused: false,
+        reject_fee_exceeding_amount: false,
+        treasury_account: None,
+        treasury_bps: 0,
+        max_subaccounts_per_principal: None,
+        tag_revocations: false,
+        min_approval_amount: None,
+        fee_oracle: None,
+        max_fee_change_bps: 0,
+        dust_consolidation_account: None,
+        dust_threshold: Nat::from(0),
+        delegations_enabled: false,
+        kyc_tier_limits: Vec::new(),
+        payment_request_ttl: None,
+        next_payment_code: 0,
+        minting_disabled: false,
+        scheduled_transfers_enabled: false,
+        next_schedule_id: 0,
+        skip_unchanged_approvals: false,
+        mint_fee: None,
+        dispute_window: None,
+        require_approval_expiry: None,
+        fee_debt_cap: None,
+        last_block_hash: None,
+        escrow_ttl: None,
+        tip_account: None,
+        aliases_enabled: None,
+        fee_collector: None,
+        default_approval_expiry: None,
+        min_reputation: None,
+        max_blocks_per_request: None,
+        largest_transaction: None,
+        batch_netting_enabled: None,
+        max_supply: None,
+        daily_summaries_enabled: None,
+        daily_totals: None,
+        min_burn_amount: None,
+        default_subaccounts_merged: None,
+        account_blocks_backfilled: None,
+    }
+}
+
 // Mock test for ICRC-1 name
 #[test]
 fn test_icrc1_name() {
@@ -143,10 +196,20 @@ fn test_icrc3_get_blocks() {
             amount: Nat::from(1000000),
             memo: None,
             created_at_time: None,
+            fee: None,
         }),
         burn: None,
         transfer: None,
         approve: None,
+        genesis: None,
+        settlement: None,
+        fee_payment: None,
+        tip: None,
+        fee_collector: None,
+        fee_change: None,
+        fee_implicit: None,
+        daily_summary: None,
+        phash: None,
         timestamp: 1000000,
     };
     
@@ -162,8 +225,18 @@ fn test_icrc3_get_blocks() {
             fee: Some(Nat::from(10000)),
             memo: None,
             created_at_time: None,
+            category: None,
         }),
         approve: None,
+        genesis: None,
+        settlement: None,
+        fee_payment: None,
+        tip: None,
+        fee_collector: None,
+        fee_change: None,
+        fee_implicit: None,
+        daily_summary: None,
+        phash: None,
         timestamp: 1000100,
     };
     
@@ -200,4 +273,2883 @@ fn test_icrc3_get_blocks() {
     
     assert_eq!(transfer_tx.kind, "transfer");
     assert!(transfer_tx.transfer.is_some());
-}
\ No newline at end of file
+}
+#[test]
+fn test_get_config() {
+    let minting_account = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+
+    let mut data = default_token_data();
+
+    // Snapshot reflects the initial values
+    let config = data.config();
+    assert_eq!(config.name, "ICRC3 Token");
+    assert_eq!(config.symbol, "ICR3");
+    assert_eq!(config.decimals, 8);
+    assert_eq!(config.fee, Nat::from(10_000));
+    assert_eq!(config.minting_account, None);
+    assert_eq!(config.supported_standards, vec!["ICRC-1", "ICRC-2", "ICRC-3"]);
+
+    // Snapshot reflects values changed after init
+    data.minting_account = Some(minting_account.clone());
+    assert_eq!(data.config().minting_account, Some(minting_account));
+}
+
+#[test]
+fn test_account_label() {
+    let owner = Principal::from_slice(&[1, 2, 3]);
+    let other = Principal::from_slice(&[4, 5, 6]);
+    let account = Account {
+        owner,
+        subaccount: Some(vec![1; 32]),
+    };
+
+    // Labeling and reading back
+    let label = AccountLabel::new("Hot wallet".to_string()).unwrap();
+    let mut labels = std::collections::BTreeMap::new();
+    labels.insert(account.clone(), label.clone());
+    assert_eq!(labels.get(&account).map(|l| l.0.clone()), Some("Hot wallet".to_string()));
+
+    // Storage round-trip
+    assert_eq!(AccountLabel::from_bytes(label.to_bytes()), label);
+
+    // Only the owner may set the label
+    assert!(AccountLabel::check_owner(&account, other).is_err());
+    assert!(AccountLabel::check_owner(&account, owner).is_ok());
+
+    // Invalid labels are rejected
+    assert!(AccountLabel::new(String::new()).is_err());
+    assert!(AccountLabel::new("x".repeat(MAX_LABEL_LENGTH + 1)).is_err());
+}
+
+#[test]
+fn test_is_retriable_error_code() {
+    let unavailable = ErrorCode::TemporarilyUnavailable as u64;
+    let insufficient = ErrorCode::InsufficientFunds as u64;
+
+    assert!(ErrorCode::from_code(unavailable).unwrap().is_retriable());
+    assert!(!ErrorCode::from_code(insufficient).unwrap().is_retriable());
+    assert!(!ErrorCode::BadFee.is_retriable());
+
+    // Unknown codes map to nothing
+    assert_eq!(ErrorCode::from_code(42), None);
+    assert_eq!(ErrorCode::Unauthorized.to_nat(), Nat::from(1));
+}
+
+#[test]
+fn test_estimate_batch_fee() {
+    let data = default_token_data();
+
+    // Fees actually charged by a batch of three transfers
+    let charged = (0..3).fold(Nat::from(0), |total, _| total + data.fee.clone());
+
+    assert_eq!(data.batch_fee(3), charged);
+    assert_eq!(data.batch_fee(0), Nat::from(0));
+}
+
+#[test]
+fn test_approvals_paused() {
+    let mut data = default_token_data();
+    assert!(!data.config().approvals_paused);
+
+    // Pausing approvals is reflected in the config while transfers stay enabled
+    data.approvals_paused = true;
+    assert!(data.config().approvals_paused);
+
+    // Unpausing restores approvals
+    data.approvals_paused = false;
+    assert!(!data.config().approvals_paused);
+}
+
+#[test]
+fn test_first_available_block() {
+    let mut blocks = std::collections::BTreeMap::new();
+    for i in 0..5u64 {
+        blocks.insert(StableBlockIndex::new(i), i);
+    }
+
+    let first = |blocks: &std::collections::BTreeMap<StableBlockIndex, u64>| {
+        blocks.keys().next().map(|index| index.to_nat())
+    };
+    assert_eq!(first(&blocks), Some(Nat::from(0)));
+
+    // Removing old blocks advances the first available index
+    blocks.remove(&StableBlockIndex::new(0));
+    blocks.remove(&StableBlockIndex::new(1));
+    assert_eq!(first(&blocks), Some(Nat::from(2)));
+
+    // Storage keys preserve the block order
+    let key = StableBlockIndex::new(2);
+    assert_eq!(StableBlockIndex::from_bytes(key.to_bytes()), key);
+}
+
+#[test]
+fn test_export_import_allowances() {
+    let owner = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let spender_a = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let spender_b = Account {
+        owner: Principal::from_slice(&[7, 8, 9]),
+        subaccount: Some(vec![1; 32]),
+    };
+
+    let mut allowances = std::collections::BTreeMap::new();
+    allowances.insert(
+        AccountPair(owner.clone(), spender_a.clone()),
+        Allowance { allowance: Nat::from(100), expires_at: Some(1_000_000) },
+    );
+    allowances.insert(
+        AccountPair(owner.clone(), spender_b.clone()),
+        Allowance { allowance: Nat::from(200), expires_at: None },
+    );
+
+    // Export one page at a time, in key order
+    let first_page: Vec<_> = allowances.iter().take(1).collect();
+    let start_after = first_page[0].0.clone();
+    let second_page: Vec<_> = allowances
+        .range((std::ops::Bound::Excluded(start_after), std::ops::Bound::Unbounded))
+        .take(1)
+        .collect();
+    let exported: Vec<(Account, Account, Allowance)> = first_page
+        .into_iter()
+        .chain(second_page)
+        .map(|(pair, allowance)| (pair.0.clone(), pair.1.clone(), allowance.clone()))
+        .collect();
+    assert_eq!(exported.len(), 2);
+
+    // Clear and re-import through stable storage encoding; entries expired by import time are skipped
+    let original = allowances.clone();
+    allowances.clear();
+    let now = 500_000;
+    let expired = (
+        owner.clone(),
+        Account { owner: Principal::from_slice(&[10, 11, 12]), subaccount: None },
+        Allowance { allowance: Nat::from(300), expires_at: Some(400_000) },
+    );
+    for (owner, spender, allowance) in exported.into_iter().chain([expired]) {
+        let allowance = Allowance::from_bytes(allowance.to_bytes());
+        if allowance.is_expired(now) {
+            continue;
+        }
+        allowances.insert(AccountPair(owner, spender), allowance);
+    }
+
+    assert_eq!(allowances, original);
+    assert_eq!(
+        allowances[&AccountPair(owner, spender_a)].expires_at,
+        Some(1_000_000)
+    );
+}
+
+#[test]
+fn test_reject_fee_exceeding_amount() {
+    let mut data = default_token_data();
+
+    // Guard off: dust transfers are allowed
+    assert!(!data.fee_exceeds_amount(&Nat::from(10_000)));
+    assert!(!data.fee_exceeds_amount(&Nat::from(5_000)));
+
+    // Guard on: amounts equal to or below the fee are rejected
+    data.reject_fee_exceeding_amount = true;
+    assert!(data.fee_exceeds_amount(&Nat::from(10_000)));
+    assert!(data.fee_exceeds_amount(&Nat::from(5_000)));
+    assert!(!data.fee_exceeds_amount(&Nat::from(10_001)));
+}
+
+#[test]
+fn test_balance_histogram() {
+    let boundaries = vec![Nat::from(100), Nat::from(1_000)];
+    let balances = [5u64, 99, 100, 500, 999, 1_000, 50_000];
+
+    let mut counts = vec![0u64; boundaries.len() + 1];
+    for balance in balances {
+        counts[histogram_bucket(&boundaries, &Nat::from(balance))] += 1;
+    }
+
+    // [0, 100), [100, 1000), [1000, ...)
+    assert_eq!(counts, vec![2, 3, 2]);
+}
+
+#[test]
+fn test_treasury_allocation_on_mint() {
+    let mut data = default_token_data();
+    let amount = Nat::from(10_000);
+
+    // No treasury configured: the recipient gets everything
+    data.treasury_bps = 1_000;
+    assert_eq!(data.treasury_share(&amount), Nat::from(0));
+
+    // 1000 bps treasury: 10% to the treasury, the rest to the recipient
+    data.treasury_account = Some(Account {
+        owner: Principal::from_slice(&[9, 9, 9]),
+        subaccount: None,
+    });
+    let treasury_amount = data.treasury_share(&amount);
+    assert_eq!(treasury_amount, Nat::from(1_000));
+    assert_eq!(amount - treasury_amount, Nat::from(9_000));
+}
+
+#[test]
+fn test_verify_account_balance() {
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let bob = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+
+    let log = vec![
+        Transaction::mint(
+            Mint { amount: Nat::from(1_000_000), to: alice.clone(), memo: None, created_at_time: None, fee: None },
+            1,
+        ),
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(200_000),
+                from: alice.clone(),
+                to: bob.clone(),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10_000)),
+                created_at_time: None,
+                category: None,
+            },
+            2,
+        ),
+        Transaction::burn(
+            Burn { amount: Nat::from(50_000), from: bob.clone(), spender: None, memo: None, created_at_time: None },
+            3,
+        ),
+    ];
+
+    let replay = |account: &Account| {
+        let (credits, debits) = log.iter().fold((Nat::from(0), Nat::from(0)), |(c, d), tx| {
+            let (credit, debit) = tx.balance_change(account);
+            (c + credit, d + debit)
+        });
+        credits - debits
+    };
+
+    // Healthy accounts match their stored balances
+    assert_eq!(replay(&alice), Nat::from(790_000));
+    assert_eq!(replay(&bob), Nat::from(150_000));
+
+    // A corrupted stored balance no longer matches the replay
+    let corrupted = Nat::from(999_999);
+    assert_ne!(replay(&alice), corrupted);
+}
+
+#[test]
+fn test_transfer_deadline() {
+    let args = TransferArgs {
+        from_subaccount: None,
+        to: Account {
+            owner: Principal::from_slice(&[4, 5, 6]),
+            subaccount: None,
+        },
+        amount: Nat::from(100_000),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+        deadline: Some(1_000),
+        category: None,
+        on_behalf_of: None,
+        tip: None,
+    };
+
+    // Before (or at) the deadline the transfer is accepted
+    assert!(!args.is_past_deadline(999));
+    assert!(!args.is_past_deadline(1_000));
+
+    // After the deadline it is rejected
+    assert!(args.is_past_deadline(1_001));
+
+    // No deadline never expires
+    let args = TransferArgs { deadline: None, ..args };
+    assert!(!args.is_past_deadline(u64::MAX));
+}
+
+#[test]
+fn test_transactions_per_interval() {
+    let interval = 1_000;
+    let now = 10_000;
+    let timestamps = [7_500u64, 8_200, 8_900, 9_100, 9_500, 9_999];
+
+    let mut counts = vec![0u64; 2];
+    for ts in timestamps {
+        if let Some(bucket) = interval_bucket(now, interval, 2, ts) {
+            counts[bucket] += 1;
+        }
+    }
+
+    // (8000, 9000] holds two transactions, (9000, 10000] holds three; 7500 is too old
+    assert_eq!(counts, vec![2, 3]);
+    assert_eq!(interval_bucket(now, 0, 2, 9_999), None);
+}
+
+#[test]
+fn test_max_subaccounts_per_principal() {
+    let mut data = default_token_data();
+
+    // No limit: any number of subaccounts is allowed
+    assert!(data.allows_new_subaccount(1_000));
+    assert_eq!(data.config().max_subaccounts_per_principal, None);
+
+    // The advertised limit is the one enforced
+    data.max_subaccounts_per_principal = Some(2);
+    let advertised = data.config().max_subaccounts_per_principal.unwrap();
+    assert!(data.allows_new_subaccount(advertised - 1));
+    assert!(!data.allows_new_subaccount(advertised));
+}
+
+#[test]
+fn test_revocation_block_tag() {
+    let approve = Approve {
+        from: Account {
+            owner: Principal::from_slice(&[1, 2, 3]),
+            subaccount: None,
+        },
+        spender: Account {
+            owner: Principal::from_slice(&[4, 5, 6]),
+            subaccount: None,
+        },
+        amount: Nat::from(0),
+        expected_allowance: None,
+        expires_at: None,
+        memo: None,
+        fee: Some(Nat::from(10_000)),
+        created_at_time: None,
+    };
+
+    // Zero approvals are tagged only when enabled
+    assert_eq!(approve.op(false), "approve");
+    assert_eq!(approve.op(true), "revoke");
+
+    // Non-zero approvals are never revocations
+    let approve = Approve { amount: Nat::from(50_000), ..approve };
+    assert_eq!(approve.op(true), "approve");
+}
+
+#[test]
+fn test_allowance_utilization() {
+    // Approve 100, then spend 40 via transfer_from
+    let approved = StableNat::from(100);
+    let remaining = Allowance {
+        allowance: approved.as_nat().clone() - Nat::from(40),
+        expires_at: None,
+    };
+
+    let spent = approved.as_nat().clone() - remaining.allowance.clone();
+    assert_eq!((spent, approved.as_nat().clone()), (Nat::from(40), Nat::from(100)));
+
+    // The approved amount survives stable storage
+    assert_eq!(StableNat::from_bytes(approved.to_bytes()), approved);
+}
+
+#[test]
+fn test_transfer_category() {
+    let from = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let to = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let transfer = |category: Option<&str>| Transfer {
+        amount: Nat::from(1_000),
+        from: from.clone(),
+        to: to.clone(),
+        spender: None,
+        memo: None,
+        fee: Some(Nat::from(10_000)),
+        created_at_time: None,
+        category: category.map(|c| c.to_string()),
+    };
+
+    let log = vec![
+        transfer(Some("payroll")),
+        transfer(Some("refund")),
+        transfer(None),
+        transfer(Some("payroll")),
+    ];
+
+    // Filtering by category returns only the tagged transfers
+    let payroll: Vec<usize> = log
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.category.as_deref() == Some("payroll"))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(payroll, vec![0, 3]);
+
+    // Category validation
+    assert!(validate_category("payroll").is_ok());
+    assert!(validate_category("q3-bonus_2024").is_ok());
+    assert!(validate_category("").is_err());
+    assert!(validate_category("Payroll").is_err());
+    assert!(validate_category(&"a".repeat(MAX_CATEGORY_LENGTH + 1)).is_err());
+}
+
+#[test]
+fn test_total_fees_paid_by() {
+    let payer = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let fee = Nat::from(10_000);
+
+    // Two transfers and one approval, each charging the fee
+    let mut fees_paid = std::collections::BTreeMap::new();
+    for _ in 0..3 {
+        let paid: StableNat = fees_paid.get(&payer).cloned().unwrap_or_else(|| StableNat::from(0));
+        fees_paid.insert(payer.clone(), paid + StableNat::from_nat(fee.clone()));
+    }
+
+    assert_eq!(fees_paid[&payer].as_nat(), &Nat::from(30_000));
+}
+
+#[test]
+fn test_min_approval_amount() {
+    let mut data = default_token_data();
+    assert!(!data.approval_below_minimum(&Nat::from(1)));
+
+    data.min_approval_amount = Some(Nat::from(1_000));
+
+    // Below the minimum is rejected; at and above are accepted
+    assert!(data.approval_below_minimum(&Nat::from(999)));
+    assert!(!data.approval_below_minimum(&Nat::from(1_000)));
+    assert!(!data.approval_below_minimum(&Nat::from(5_000)));
+
+    // Zero is always allowed as a revocation
+    assert!(!data.approval_below_minimum(&Nat::from(0)));
+}
+
+#[test]
+fn test_recent_large_transfers() {
+    let from = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let to = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let amounts = [500u64, 2_000_000, 700, 5_000_000, 1_000_000];
+    let log: Vec<Transaction> = amounts
+        .iter()
+        .enumerate()
+        .map(|(i, amount)| {
+            Transaction::transfer(
+                Transfer {
+                    amount: Nat::from(*amount),
+                    from: from.clone(),
+                    to: to.clone(),
+                    spender: None,
+                    memo: None,
+                    fee: Some(Nat::from(10_000)),
+                    created_at_time: None,
+                    category: None,
+                },
+                i as u64,
+            )
+        })
+        .collect();
+
+    // Only transfers strictly above the threshold are reported
+    let threshold = Nat::from(1_000_000);
+    let large: Vec<usize> = log
+        .iter()
+        .enumerate()
+        .filter(|(_, tx)| tx.transfer.as_ref().map(|t| t.amount > threshold).unwrap_or(false))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(large, vec![1, 3]);
+}
+
+#[test]
+fn test_fee_oracle_bounds() {
+    let mut token_data = default_token_data();
+    token_data.fee_oracle = Some(Principal::from_slice(&[9, 9, 9]));
+    token_data.max_fee_change_bps = 1_000; // 10% per update
+
+    // Proposals within the bound are adopted as-is
+    assert_eq!(token_data.bounded_fee(&Nat::from(10_500)), Nat::from(10_500));
+
+    // Proposals outside the bound are clamped to it
+    assert_eq!(token_data.bounded_fee(&Nat::from(50_000)), Nat::from(11_000));
+    assert_eq!(token_data.bounded_fee(&Nat::from(1)), Nat::from(9_000));
+
+    // Repeated polls converge on the oracle's fee
+    for _ in 0..20 {
+        token_data.fee = token_data.bounded_fee(&Nat::from(20_000));
+    }
+    assert_eq!(token_data.fee, Nat::from(20_000));
+
+    // Small fees still move by at least one unit per update, including a zero fee
+    token_data.fee = Nat::from(5);
+    assert_eq!(token_data.bounded_fee(&Nat::from(100)), Nat::from(6));
+    assert_eq!(token_data.bounded_fee(&Nat::from(0)), Nat::from(4));
+    token_data.fee = Nat::from(0);
+    assert_eq!(token_data.bounded_fee(&Nat::from(100)), Nat::from(1));
+    token_data.fee = Nat::from(20_000);
+
+    // Without a change bound the fee is frozen
+    token_data.max_fee_change_bps = 0;
+    assert_eq!(token_data.bounded_fee(&Nat::from(1)), Nat::from(20_000));
+}
+
+#[test]
+fn test_subaccount_transactions() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let owner = Principal::from_slice(&[1, 2, 3]);
+    let savings = Account {
+        owner,
+        subaccount: Some(vec![1; 32]),
+    };
+    let spending = Account {
+        owner,
+        subaccount: Some(vec![2; 32]),
+    };
+    let other = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let transfer = |from: &Account, to: &Account, timestamp: u64| {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(1_000),
+                from: from.clone(),
+                to: to.clone(),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10_000)),
+                created_at_time: None,
+                category: None,
+            },
+            timestamp,
+        )
+    };
+    let log = vec![
+        Transaction::mint(
+            Mint {
+                amount: Nat::from(100_000),
+                to: savings.clone(),
+                memo: None,
+                created_at_time: None,
+                fee: None,
+            },
+            0,
+        ),
+        transfer(&other, &spending, 1),
+        transfer(&savings, &other, 2),
+        transfer(&spending, &other, 3),
+    ];
+
+    // Index the log the way the per-account index is backfilled after an upgrade
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut index: StableBTreeMap<AccountBlock, (), _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    for (block, tx) in log.iter().enumerate() {
+        index_account_blocks(&mut index, tx, block as u64);
+    }
+    let blocks_of = |account: &Account| account_blocks(&index, account, 0, 10);
+
+    // Each subaccount only sees its own activity
+    assert_eq!(blocks_of(&savings), vec![0, 2]);
+    assert_eq!(blocks_of(&spending), vec![1, 3]);
+
+    // The default subaccount of the same principal had no activity
+    let default = Account {
+        owner,
+        subaccount: None,
+    };
+    assert!(blocks_of(&default).is_empty());
+}
+
+#[test]
+fn test_dust_consolidation() {
+    let mut token_data = default_token_data();
+    let holder = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let collector = Account {
+        owner: Principal::from_slice(&[7, 7, 7]),
+        subaccount: None,
+    };
+
+    // Disabled by default
+    assert_eq!(token_data.dust_consolidation_target(&holder, &Nat::from(5)), None);
+
+    token_data.dust_consolidation_account = Some(collector.clone());
+    token_data.dust_threshold = Nat::from(1_000);
+
+    // A sub-threshold receipt is swept into the consolidation account
+    assert_eq!(
+        token_data.dust_consolidation_target(&holder, &Nat::from(999)),
+        Some(collector.clone())
+    );
+
+    // Balances at or above the threshold stay put
+    assert_eq!(token_data.dust_consolidation_target(&holder, &Nat::from(1_000)), None);
+
+    // The consolidation account never sweeps into itself
+    assert_eq!(token_data.dust_consolidation_target(&collector, &Nat::from(5)), None);
+
+    // The sweep moves the whole balance out of the holder
+    let sweep = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(999),
+            from: holder.clone(),
+            to: collector.clone(),
+            spender: None,
+            memo: None,
+            fee: None,
+            created_at_time: None,
+            category: Some("dust".to_string()),
+        },
+        0,
+    );
+    assert_eq!(sweep.balance_change(&holder), (Nat::from(0), Nat::from(999)));
+    assert_eq!(sweep.balance_change(&collector), (Nat::from(999), Nat::from(0)));
+}
+
+#[test]
+fn test_balance_delta() {
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let bob = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let transfer = |from: &Account, to: &Account, amount: u64| {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(amount),
+                from: from.clone(),
+                to: to.clone(),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10)),
+                created_at_time: None,
+                category: None,
+            },
+            0,
+        )
+    };
+    let log = vec![
+        transfer(&alice, &bob, 1_000),
+        transfer(&bob, &alice, 300),
+        transfer(&alice, &bob, 500),
+    ];
+    let delta_since = |account: &Account, since: usize| {
+        log[since..]
+            .iter()
+            .fold(Int::from(0), |delta, tx| delta + tx.balance_delta(account))
+    };
+
+    // Net inflow for the recipient
+    assert_eq!(delta_since(&bob, 0), Int::from(1_000 - 310 + 500));
+
+    // Net outflow for the sender, fees included
+    assert_eq!(delta_since(&alice, 0), Int::from(-1_010 + 300 - 510));
+
+    // Only blocks from the snapshot onward count
+    assert_eq!(delta_since(&alice, 2), Int::from(-510));
+    assert_eq!(delta_since(&alice, 3), Int::from(0));
+}
+
+#[test]
+fn test_activity_health() {
+    // An empty log reports no gap
+    assert_eq!(activity_gap(1_000, None), 0);
+
+    // The gap grows while nothing is recorded
+    let last_block = 1_000;
+    let first_gap = activity_gap(5_000, Some(last_block));
+    let second_gap = activity_gap(9_000, Some(last_block));
+    assert_eq!(first_gap, 4_000);
+    assert!(second_gap > first_gap);
+
+    // A new block resets it
+    assert_eq!(activity_gap(9_000, Some(9_000)), 0);
+
+    // Clock skew never underflows
+    assert_eq!(activity_gap(500, Some(1_000)), 0);
+}
+
+#[test]
+fn test_delegation_cap() {
+    let fee = Nat::from(10_000);
+    let mut delegation = Allowance {
+        allowance: Nat::from(100_000),
+        expires_at: Some(5_000),
+    };
+
+    // A transfer within the cap is allowed and draws the cap down by amount plus fee
+    let first = Nat::from(60_000) + fee.clone();
+    assert!(delegation.covers(&first, 1_000));
+    delegation.allowance -= first;
+    assert_eq!(delegation.allowance, Nat::from(30_000));
+
+    // A transfer past the remaining cap is rejected
+    let second = Nat::from(25_000) + fee.clone();
+    assert!(!delegation.covers(&second, 1_000));
+
+    // The remaining cap can still be used exactly
+    assert!(delegation.covers(&Nat::from(30_000), 1_000));
+
+    // Nothing is covered once the delegation expires
+    assert!(!delegation.covers(&Nat::from(1), 5_001));
+
+    // Delegation failures surface through GenericError codes
+    assert_eq!(ErrorCode::from_code(7), Some(ErrorCode::DelegationExceeded));
+}
+
+#[test]
+fn test_blocks_versioned_tip() {
+    // The tip tag is stable while the log is unchanged
+    let tip = log_tip_tag(&Nat::from(42));
+    assert_eq!(tip, log_tip_tag(&Nat::from(42)));
+
+    // Appending a block yields a new tag, so pollers fetch again
+    assert_ne!(tip, log_tip_tag(&Nat::from(43)));
+
+    // A poller holding the current tip gets the no-change response
+    let unchanged = GetBlocksVersioned {
+        tip: tip.clone(),
+        blocks: None,
+    };
+    assert!(unchanged.blocks.is_none());
+    assert_eq!(unchanged.tip, tip);
+}
+
+#[test]
+fn test_kyc_tier_limits() {
+    let mut token_data = default_token_data();
+
+    // No limits are enforced until a tier table is configured
+    assert_eq!(token_data.kyc_limit(0), None);
+
+    token_data.kyc_tier_limits = vec![Nat::from(1_000), Nat::from(100_000), Nat::from(10_000_000)];
+
+    // Unknown accounts fall back to tier 0, the most restrictive one
+    let unknown_tier = 0;
+    assert_eq!(token_data.kyc_limit(unknown_tier), Some(Nat::from(1_000)));
+
+    // Each tier enforces its own limit
+    let within = |tier: u8, amount: u64| token_data.kyc_limit(tier).map(|limit| limit >= amount).unwrap_or(true);
+    assert!(within(0, 1_000));
+    assert!(!within(0, 1_001));
+    assert!(within(1, 50_000));
+    assert!(!within(1, 200_000));
+    assert!(within(2, 5_000_000));
+
+    // Tiers above the table get the highest configured limit
+    assert_eq!(token_data.kyc_limit(7), Some(Nat::from(10_000_000)));
+
+    // Only ascending tables are accepted, keeping tier 0 the most restrictive
+    assert!(validate_kyc_tier_limits(&token_data.kyc_tier_limits).is_ok());
+    assert!(validate_kyc_tier_limits(&[Nat::from(1_000), Nat::from(1_000)]).is_ok());
+    assert!(validate_kyc_tier_limits(&[Nat::from(100_000), Nat::from(1_000)]).is_err());
+}
+
+#[test]
+fn test_batch_approve_results() {
+    let token_data = default_token_data();
+    let approval = |spender: u8, fee: Option<u64>| ApproveArgs {
+        from_subaccount: None,
+        spender: Account {
+            owner: Principal::from_slice(&[spender]),
+            subaccount: None,
+        },
+        amount: Nat::from(50_000),
+        expected_allowance: None,
+        expires_at: None,
+        fee: fee.map(Nat::from),
+        memo: None,
+        created_at_time: None,
+    };
+    let batch = vec![
+        approval(1, None),
+        approval(2, Some(1)),
+        approval(3, Some(10_000)),
+        approval(1, None),
+    ];
+
+    // Simulate per-entry processing: bad fees fail alone, duplicates point at the accepted entry
+    let mut accepted: Vec<(ApproveArgs, Nat)> = Vec::new();
+    let mut results = Vec::new();
+    for args in batch {
+        if let Some(duplicate_of) = duplicate_in_batch(&accepted, &args) {
+            results.push(ApproveResult::Err(ApproveError::Duplicate { duplicate_of }));
+            continue;
+        }
+        let fee = args.fee.clone().unwrap_or_else(|| token_data.fee.clone());
+        if fee != token_data.fee {
+            results.push(ApproveResult::Err(ApproveError::BadFee {
+                expected_fee: token_data.fee.clone(),
+            }));
+            continue;
+        }
+        let block_index = Nat::from(accepted.len());
+        accepted.push((args, block_index.clone()));
+        results.push(ApproveResult::Ok(block_index));
+    }
+
+    assert_eq!(results[0], ApproveResult::Ok(Nat::from(0)));
+    assert_eq!(
+        results[1],
+        ApproveResult::Err(ApproveError::BadFee {
+            expected_fee: Nat::from(10_000)
+        })
+    );
+    assert_eq!(results[2], ApproveResult::Ok(Nat::from(1)));
+    assert_eq!(
+        results[3],
+        ApproveResult::Err(ApproveError::Duplicate {
+            duplicate_of: Nat::from(0)
+        })
+    );
+}
+
+#[test]
+fn test_block_context_range() {
+    // Around a middle block the context is centred on it
+    assert_eq!(context_range(50, 3), (47, 7));
+
+    // Around the first block the start is clamped to the beginning of the log
+    assert_eq!(context_range(0, 3), (0, 4));
+    assert_eq!(context_range(1, 3), (0, 5));
+
+    // A zero radius returns just the block itself
+    assert_eq!(context_range(10, 0), (10, 1));
+
+    // Huge indices never overflow
+    let (start, length) = context_range(u64::MAX, 2);
+    assert_eq!(start, u64::MAX - 2);
+    assert_eq!(length, 2);
+}
+
+#[test]
+fn test_genesis_block() {
+    let token_data = default_token_data();
+
+    // Genesis emission is off unless requested at init
+    assert_eq!(InitArgs::default().emit_genesis_block, None);
+
+    // The genesis block carries the initial configuration
+    let genesis = Transaction::genesis(token_data.genesis(), 1_000);
+    assert_eq!(genesis.kind, "genesis");
+    let config = genesis.genesis.clone().unwrap();
+    assert_eq!(config.name, "ICRC3 Token");
+    assert_eq!(config.symbol, "ICR3");
+    assert_eq!(config.decimals, 8);
+    assert_eq!(config.fee, Nat::from(10_000));
+
+    // It moves no funds
+    let anyone = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    assert_eq!(genesis.balance_change(&anyone), (Nat::from(0), Nat::from(0)));
+    assert!(!genesis.accounts().contains(&anyone));
+
+    // It survives the stable-storage round trip
+    let restored = Transaction::from_bytes(genesis.to_bytes());
+    assert_eq!(restored, genesis);
+}
+
+#[test]
+fn test_payment_request_single_use() {
+    let mut request = PaymentRequest {
+        merchant: Account {
+            owner: Principal::from_slice(&[7, 7, 7]),
+            subaccount: None,
+        },
+        amount: Nat::from(25_000),
+        memo: Some(b"order-42".to_vec()),
+        expires_at: 5_000,
+        paid_in: None,
+    };
+
+    // An unpaid, unexpired request can be paid
+    assert_eq!(request.check_payable(1_000), Ok(()));
+
+    // Paying consumes the code; a second payment is rejected as a duplicate
+    request.paid_in = Some(Nat::from(12));
+    assert_eq!(
+        request.check_payable(1_000),
+        Err(TransferError::Duplicate {
+            duplicate_of: Nat::from(12)
+        })
+    );
+
+    // An unpaid request past its expiry is rejected
+    request.paid_in = None;
+    match request.check_payable(5_001) {
+        Err(TransferError::GenericError { error_code, .. }) => {
+            assert_eq!(error_code, ErrorCode::PaymentCodeExpired.to_nat())
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Requests round-trip through stable storage
+    assert_eq!(PaymentRequest::from_bytes(request.to_bytes()), request);
+}
+
+#[test]
+fn test_disable_minting() {
+    let mut token_data = default_token_data();
+    let mint = |data: &mut TokenData, amount: u64| -> Result<(), ErrorCode> {
+        if data.minting_disabled {
+            return Err(ErrorCode::MintingDisabled);
+        }
+        data.total_supply += Nat::from(amount);
+        Ok(())
+    };
+
+    // Minting works until disabled
+    assert_eq!(mint(&mut token_data, 1_000_000), Ok(()));
+    assert_eq!(token_data.total_supply, Nat::from(1_000_000));
+
+    // Once disabled, further mints fail and the supply stays fixed
+    token_data.minting_disabled = true;
+    assert_eq!(mint(&mut token_data, 1), Err(ErrorCode::MintingDisabled));
+    assert_eq!(token_data.total_supply, Nat::from(1_000_000));
+
+    // Holders can see the guarantee in the config
+    assert!(token_data.config().minting_disabled);
+    assert_eq!(ErrorCode::from_code(12), Some(ErrorCode::MintingDisabled));
+}
+
+#[test]
+fn test_allowance_exposure() {
+    let total_supply = Nat::from(1_000_000);
+    let allowances = vec![
+        Allowance {
+            allowance: Nat::from(800_000),
+            expires_at: None,
+        },
+        Allowance {
+            allowance: Nat::from(700_000),
+            expires_at: Some(10_000),
+        },
+        Allowance {
+            allowance: Nat::from(5_000_000),
+            expires_at: Some(100),
+        },
+    ];
+    let now = 1_000;
+
+    // Expired allowances are not counted
+    let committed = allowances
+        .iter()
+        .filter(|allowance| !allowance.is_expired(now))
+        .fold(Nat::from(0), |sum, allowance| sum + allowance.allowance.clone());
+    assert_eq!(committed, Nat::from(1_500_000));
+
+    // Approvals exceed the supply, signalling over-approval
+    assert!(committed > total_supply);
+}
+
+#[test]
+fn test_scheduled_transfer() {
+    let scheduled = ScheduledTransfer {
+        from: Account {
+            owner: Principal::from_slice(&[1, 2, 3]),
+            subaccount: None,
+        },
+        to: Account {
+            owner: Principal::from_slice(&[4, 5, 6]),
+            subaccount: None,
+        },
+        amount: Nat::from(500_000),
+        fee: Nat::from(10_000),
+        memo: None,
+        execute_at: 2_000,
+    };
+
+    // The sender's amount and fee are locked until execution
+    assert_eq!(scheduled.locked_amount(), Nat::from(510_000));
+
+    // Not executed before its time, executed once time advances
+    assert!(!scheduled.is_due(1_999));
+    assert!(scheduled.is_due(2_000));
+    assert!(scheduled.is_due(5_000));
+
+    // Pending transfers sort by execution time, so due ones come first
+    let mut pending = vec![(3_000u64, 0u64), (2_000, 1), (2_000, 2)];
+    pending.sort();
+    let due: Vec<_> = pending.iter().filter(|(at, _)| *at <= 2_500).collect();
+    assert_eq!(due, vec![&(2_000, 1), &(2_000, 2)]);
+
+    // Scheduled transfers round-trip through stable storage
+    assert_eq!(ScheduledTransfer::from_bytes(scheduled.to_bytes()), scheduled);
+}
+
+#[test]
+fn test_find_blocks_by_memo() {
+    let from = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let merchant = Account {
+        owner: Principal::from_slice(&[7, 7, 7]),
+        subaccount: None,
+    };
+    let log: Vec<Transaction> = ["INV-1001", "INV-1002", "INV-10020"]
+        .iter()
+        .map(|invoice| {
+            Transaction::transfer(
+                Transfer {
+                    amount: Nat::from(1_000),
+                    from: from.clone(),
+                    to: merchant.clone(),
+                    spender: None,
+                    memo: Some(invoice.as_bytes().to_vec()),
+                    fee: Some(Nat::from(10_000)),
+                    created_at_time: None,
+                    category: None,
+                },
+                0,
+            )
+        })
+        .collect();
+
+    // Only the exact memo matches, not prefixes or extensions of it
+    let wanted = b"INV-1002".to_vec();
+    let matches: Vec<usize> = log
+        .iter()
+        .enumerate()
+        .filter(|(_, tx)| tx.memo() == Some(&wanted))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(matches, vec![1]);
+
+    // Blocks without a memo never match
+    let approve = Transaction::approve(
+        Approve {
+            from: from.clone(),
+            spender: merchant.clone(),
+            amount: Nat::from(1),
+            expected_allowance: None,
+            expires_at: None,
+            memo: None,
+            fee: None,
+            created_at_time: None,
+        },
+        0,
+    );
+    assert_eq!(approve.memo(), None);
+}
+
+#[test]
+fn test_skip_unchanged_approvals() {
+    let mut token_data = default_token_data();
+    token_data.skip_unchanged_approvals = true;
+
+    let mut current: Option<Allowance> = None;
+    let mut log_length = 0u64;
+    let mut last_block = None;
+    let mut approve = |allowance: Allowance| -> u64 {
+        if token_data.skip_unchanged_approvals && current.as_ref() == Some(&allowance) {
+            if let Some(block) = last_block {
+                return block;
+            }
+        }
+        current = Some(allowance);
+        let block = log_length;
+        log_length += 1;
+        last_block = Some(block);
+        block
+    };
+    let allowance = Allowance {
+        allowance: Nat::from(50_000),
+        expires_at: Some(9_000),
+    };
+
+    // Approving identically twice records a single block and returns it both times
+    assert_eq!(approve(allowance.clone()), 0);
+    assert_eq!(approve(allowance.clone()), 0);
+
+    // A different expiry is a real change and records a new block
+    assert_eq!(
+        approve(Allowance {
+            expires_at: None,
+            ..allowance
+        }),
+        1
+    );
+    assert_eq!(log_length, 2);
+}
+
+#[test]
+fn test_most_dormant_accounts() {
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[id]),
+        subaccount: None,
+    };
+
+    // Last activity comes from every account a block touches
+    let tx = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(1),
+            from: account(1),
+            to: account(2),
+            spender: Some(account(3)),
+            memo: None,
+            fee: None,
+            created_at_time: None,
+            category: None,
+        },
+        0,
+    );
+    assert_eq!(tx.accounts(), vec![account(1), account(2), account(3)]);
+
+    let entries = vec![
+        (account(1), 5_000),
+        (account(2), 1_000),
+        (account(3), 9_000),
+        (account(4), 3_000),
+    ];
+
+    // The most dormant accounts come first
+    let dormant = most_dormant(entries.clone(), 2);
+    assert_eq!(dormant, vec![(account(2), 1_000), (account(4), 3_000)]);
+
+    // Asking for more than exist returns everything in order
+    assert_eq!(most_dormant(entries, 10).len(), 4);
+}
+
+#[test]
+fn test_mint_fee() {
+    let mut token_data = default_token_data();
+    let treasury = Account {
+        owner: Principal::from_slice(&[9, 9, 9]),
+        subaccount: None,
+    };
+
+    // Without a mint fee or treasury the recipient gets everything
+    assert_eq!(token_data.mint_split(&Nat::from(1_000_000)), (Nat::from(1_000_000), Nat::from(0)));
+
+    // The mint fee comes off the top and goes to the treasury
+    token_data.mint_fee = Some(Nat::from(5_000));
+    token_data.treasury_account = Some(treasury);
+    assert_eq!(token_data.mint_split(&Nat::from(1_000_000)), (Nat::from(995_000), Nat::from(5_000)));
+
+    // The treasury share applies to what is left after the fee
+    token_data.treasury_bps = 1_000; // 10%
+    let (recipient, treasury_amount) = token_data.mint_split(&Nat::from(1_005_000));
+    assert_eq!(recipient, Nat::from(900_000));
+    assert_eq!(treasury_amount, Nat::from(105_000));
+
+    // Nothing is created beyond the requested amount
+    assert_eq!(recipient + treasury_amount, Nat::from(1_005_000));
+
+    // The fee goes to the treasury, so no fee collector is recorded for it
+    let collector = Account {
+        owner: Principal::from_slice(&[7, 7, 7]),
+        subaccount: None,
+    };
+    token_data.fee_collector = Some(collector.clone());
+    assert_eq!(token_data.mint_fee_collector(), None);
+
+    // Without a treasury the fee goes to the fee collector instead, recorded in the mint block
+    token_data.treasury_account = None;
+    assert_eq!(token_data.mint_split(&Nat::from(1_000_000)), (Nat::from(995_000), Nat::from(0)));
+    assert_eq!(token_data.mint_fee_collector(), Some(collector.clone()));
+    let recipient = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let mut block = Transaction::mint(
+        Mint {
+            amount: Nat::from(995_000),
+            to: recipient.clone(),
+            memo: None,
+            created_at_time: None,
+            fee: Some(Nat::from(5_000)),
+        },
+        0,
+    );
+    block.fee_collector = token_data.mint_fee_collector();
+    assert_eq!(block.balance_change(&recipient), (Nat::from(995_000), Nat::from(0)));
+    assert_eq!(block.balance_change(&collector), (Nat::from(5_000), Nat::from(0)));
+    assert_eq!(block.supply_change(), (Nat::from(1_000_000), Nat::from(0)));
+
+    // With neither, the fee is simply not minted
+    token_data.fee_collector = None;
+    assert_eq!(token_data.mint_fee_collector(), None);
+}
+
+#[test]
+fn test_supply_concentration() {
+    // A skewed distribution: one whale and a long tail
+    let balances: Vec<Nat> = [9_000_000u64, 50_000, 400_000, 10, 500_000, 49_990]
+        .iter()
+        .map(|b| Nat::from(*b))
+        .collect();
+    let total_supply = balances.iter().fold(Nat::from(0), |sum, b| sum + b.clone());
+    assert_eq!(total_supply, Nat::from(10_000_000));
+
+    // Top 1 and top 3 holders
+    assert_eq!(top_balances_sum(balances.clone().into_iter(), 1), Nat::from(9_000_000));
+    assert_eq!(top_balances_sum(balances.clone().into_iter(), 3), Nat::from(9_900_000));
+
+    // Asking for more holders than exist sums everything; zero holders sum to nothing
+    assert_eq!(top_balances_sum(balances.clone().into_iter(), 100), total_supply);
+    assert_eq!(top_balances_sum(balances.into_iter(), 0), Nat::from(0));
+}
+
+#[test]
+fn test_token_data_survives_upgrade() {
+    let mut token_data = default_token_data();
+
+    // Mint and record a few blocks
+    token_data.total_supply += Nat::from(1_500_000);
+    token_data.next_block_index += Nat::from(3);
+    token_data.fee = Nat::from(20_000);
+    token_data.minting_account = Some(Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    });
+
+    // Simulate the pre_upgrade save and post_upgrade restore
+    let restored = TokenData::from_bytes(token_data.to_bytes());
+
+    assert_eq!(restored.total_supply, Nat::from(1_500_000));
+    assert_eq!(restored.next_block_index, Nat::from(3));
+    assert_eq!(restored.fee, token_data.fee);
+    assert_eq!(restored.name, token_data.name);
+    assert_eq!(restored.minting_account, token_data.minting_account);
+    assert_eq!(restored.config(), token_data.config());
+}
+
+#[test]
+fn test_reversible_transfers() {
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let recipient = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let held = ledger_account(Principal::from_slice(&[10, 11, 12]), DISPUTE_TAG);
+    let transfer = |from: &Account, to: &Account, fee: Option<Nat>| {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(100_000),
+                from: from.clone(),
+                to: to.clone(),
+                spender: None,
+                memo: None,
+                fee,
+                created_at_time: None,
+                category: None,
+            },
+            1_000,
+        )
+    };
+    let pending = PendingTransfer {
+        from: sender.clone(),
+        to: recipient.clone(),
+        amount: Nat::from(100_000),
+        final_at: 5_000,
+        payment_code: None,
+    };
+
+    // Within the window the sender may reverse; afterwards the transfer finalizes
+    assert!(pending.is_reversible(4_999));
+    assert!(!pending.is_reversible(5_000));
+
+    // While held, the amount sits in the dispute account rather than with the recipient
+    let hold = transfer(&sender, &held, Some(Nat::from(10_000)));
+    assert_eq!(hold.balance_delta(&recipient), Int::from(0));
+    assert_eq!(hold.balance_delta(&held), Int::from(100_000));
+
+    // A reversal returns the amount to the sender; only the fee is lost
+    let reversal = transfer(&held, &sender, None);
+    let net = |account: &Account| hold.balance_delta(account) + reversal.balance_delta(account);
+    assert_eq!(net(&sender), Int::from(-10_000));
+    assert_eq!(net(&recipient), Int::from(0));
+    assert_eq!(net(&held), Int::from(0));
+
+    // A finalization credits the recipient only once the window closes
+    let finalization = transfer(&held, &recipient, None);
+    assert_eq!(finalization.balance_delta(&recipient), Int::from(100_000));
+    assert_eq!(hold.balance_delta(&held) + finalization.balance_delta(&held), Int::from(0));
+}
+
+#[test]
+fn test_deduplication_key() {
+    const TX_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+    let caller = Principal::from_slice(&[1, 2, 3]);
+    let key = DedupKey {
+        created_at_time: 1_000,
+        kind: "transfer".to_string(),
+        caller,
+        from: Account {
+            owner: caller,
+            subaccount: None,
+        },
+        to: Account {
+            owner: Principal::from_slice(&[4, 5, 6]),
+            subaccount: None,
+        },
+        amount: Nat::from(100_000),
+        fee: Nat::from(10_000),
+        memo: Some(vec![7]),
+    };
+
+    // A retry with the same arguments maps to the same entry; any difference is a new transaction
+    assert_eq!(key, key.clone());
+    let other_memo = DedupKey {
+        memo: Some(vec![8]),
+        ..key.clone()
+    };
+    assert_ne!(key, other_memo);
+    let later = DedupKey {
+        created_at_time: 2_000,
+        ..key.clone()
+    };
+    assert!(key < later);
+
+    // Entries expire together with the transaction window
+    assert!(!key.is_expired(1_000 + TX_WINDOW, TX_WINDOW));
+    assert!(key.is_expired(1_001 + TX_WINDOW, TX_WINDOW));
+}
+
+#[test]
+fn test_block_range_for_time() {
+    // Blocks 10..16 with non-decreasing timestamps
+    let timestamps = [100u64, 200, 200, 300, 400, 500];
+    let timestamp_at = |index: u64| timestamps[(index - 10) as usize];
+    let range = |from_ts: u64, to_ts: u64| {
+        let first = first_index_where(10, 16, |index| timestamp_at(index) >= from_ts);
+        let past_last = first_index_where(first, 16, |index| timestamp_at(index) > to_ts);
+        (first < past_last).then(|| (first, past_last - 1))
+    };
+
+    assert_eq!(range(200, 300), Some((11, 13)));
+    assert_eq!(range(150, 450), Some((11, 14)));
+    assert_eq!(range(0, 1_000), Some((10, 15)));
+    assert_eq!(range(210, 290), None);
+    assert_eq!(range(600, 700), None);
+}
+
+#[test]
+fn test_disabled_methods() {
+    let mut disabled = std::collections::BTreeSet::new();
+    disabled.insert(MethodName::new("icrc2_approve".to_string()).unwrap());
+
+    // Only the listed method is switched off
+    assert!(disabled.contains(&MethodName("icrc2_approve".to_string())));
+    assert!(!disabled.contains(&MethodName("icrc1_transfer".to_string())));
+
+    // Unknown names are rejected rather than silently ignored
+    assert!(MethodName::new("icrc1_transfr".to_string()).is_err());
+    for method in DISABLEABLE_METHODS {
+        assert!(MethodName::new(method.to_string()).is_ok());
+    }
+}
+
+#[test]
+fn test_allowance_edges() {
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[id]),
+        subaccount: None,
+    };
+    let now = 5_000;
+
+    let mut allowances = std::collections::BTreeMap::new();
+    for (owner, spender, amount, expires_at) in [
+        (1, 2, 100u64, None),
+        (1, 3, 200, Some(4_000)),
+        (1, 4, 0, None),
+        (2, 3, 300, Some(6_000)),
+        (3, 1, 400, None),
+    ] {
+        allowances.insert(
+            AccountPair(account(owner), account(spender)),
+            Allowance { allowance: Nat::from(amount), expires_at },
+        );
+    }
+
+    // Page through live edges two at a time, continuing after the last returned pair
+    let mut edges = Vec::new();
+    let mut start_after: Option<AccountPair> = None;
+    loop {
+        let start = match start_after.clone() {
+            Some(pair) => std::ops::Bound::Excluded(pair),
+            None => std::ops::Bound::Unbounded,
+        };
+        let page: Vec<(Account, Account, Nat)> = allowances
+            .range((start, std::ops::Bound::Unbounded))
+            .filter(|(_, allowance)| !allowance.is_expired(now) && allowance.allowance > 0u64)
+            .take(2)
+            .map(|(pair, allowance)| (pair.0.clone(), pair.1.clone(), allowance.allowance.clone()))
+            .collect();
+        if page.is_empty() {
+            break;
+        }
+        let (owner, spender, _) = page.last().unwrap().clone();
+        start_after = Some(AccountPair(owner, spender));
+        edges.extend(page);
+    }
+
+    assert_eq!(
+        edges,
+        vec![
+            (account(1), account(2), Nat::from(100)),
+            (account(2), account(3), Nat::from(300)),
+            (account(3), account(1), Nat::from(400)),
+        ]
+    );
+}
+
+#[test]
+fn test_block_json() {
+    let from = Principal::from_slice(&[1, 2, 3]);
+    let to = Principal::from_slice(&[4, 5, 6]);
+    let block = Value::Map(vec![
+        ("ts".to_string(), Value::Nat64(1_000)),
+        ("op".to_string(), Value::Text("xfer".to_string())),
+        ("from".to_string(), Value::Array(vec![Value::Blob(from.as_slice().to_vec())])),
+        (
+            "to".to_string(),
+            Value::Array(vec![Value::Blob(to.as_slice().to_vec()), Value::Blob(vec![0xab; 2])]),
+        ),
+        ("amt".to_string(), Value::Nat(Nat::from(1_000_000u64))),
+        ("memo".to_string(), Value::Blob(vec![0xde, 0xad, 0xbe, 0xef])),
+    ]);
+
+    let json = block.to_json();
+    assert!(json.starts_with("{\"ts\":\"1000\",\"op\":\"xfer\""));
+    assert!(json.contains(&format!("\"from\":{{\"owner\":\"{}\",\"subaccount\":null}}", from)));
+    assert!(json.contains(&format!("\"to\":{{\"owner\":\"{}\",\"subaccount\":\"abab\"}}", to)));
+    assert!(json.contains("\"amt\":\"1000000\""));
+    assert!(json.contains("\"memo\":\"deadbeef\""));
+
+    // Text is escaped so the output stays valid JSON
+    assert_eq!(Value::Text("a\"b\\c\n".to_string()).to_json(), "\"a\\\"b\\\\c\\u000a\"");
+}
+
+#[test]
+fn test_memo_length() {
+    // A 32-byte memo is accepted, a 33-byte memo is rejected
+    assert_eq!(validate_memo(Some(&[7u8; 32])), Ok(()));
+    assert!(validate_memo(Some(&[7u8; 33])).is_err());
+    assert_eq!(validate_memo(None), Ok(()));
+    assert_eq!(ErrorCode::from_code(13), Some(ErrorCode::InvalidMemo));
+}
+
+#[test]
+fn test_mint_burn_summary() {
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+
+    let log = vec![
+        Transaction::mint(
+            Mint { amount: Nat::from(1_000_000), to: alice.clone(), memo: None, created_at_time: None, fee: None },
+            1,
+        ),
+        Transaction::burn(
+            Burn { amount: Nat::from(50_000), from: alice.clone(), spender: None, memo: None, created_at_time: None },
+            2,
+        ),
+        Transaction::mint(
+            Mint { amount: Nat::from(300_000), to: alice.clone(), memo: None, created_at_time: None, fee: None },
+            3,
+        ),
+        Transaction::burn(
+            Burn { amount: Nat::from(20_000), from: alice.clone(), spender: None, memo: None, created_at_time: None },
+            4,
+        ),
+    ];
+
+    // Totals over blocks 1..4 leave out the first mint
+    let (minted, burned) = log[1..4].iter().fold((Nat::from(0), Nat::from(0)), |(minted, burned), tx| {
+        let (tx_minted, tx_burned) = tx.supply_change();
+        (minted + tx_minted, burned + tx_burned)
+    });
+    assert_eq!(minted, Nat::from(300_000));
+    assert_eq!(burned, Nat::from(70_000));
+}
+
+#[test]
+fn test_expired_allowance_reads_zero() {
+    let allowance = Allowance {
+        allowance: Nat::from(500_000),
+        expires_at: Some(1_000),
+    };
+
+    // Before expiry the allowance is reported as stored
+    assert_eq!(allowance.clone().effective(999), allowance);
+
+    // After expiry it reads as zero with no expiry
+    assert_eq!(
+        allowance.effective(1_001),
+        Allowance {
+            allowance: Nat::from(0),
+            expires_at: None,
+        }
+    );
+}
+
+#[test]
+fn test_require_approval_expiry() {
+    let mut data = default_token_data();
+    assert!(!data.approval_missing_expiry(&Nat::from(1_000), None));
+
+    data.require_approval_expiry = Some(true);
+
+    // Without an expiry the approval is rejected; with one it is accepted
+    assert!(data.approval_missing_expiry(&Nat::from(1_000), None));
+    assert!(!data.approval_missing_expiry(&Nat::from(1_000), Some(5_000)));
+
+    // Revocations are exempt
+    assert!(!data.approval_missing_expiry(&Nat::from(0), None));
+    assert_eq!(ErrorCode::from_code(14), Some(ErrorCode::ApprovalExpiryRequired));
+}
+
+#[test]
+fn test_icrc3_get_archives() {
+    let ledger = Principal::from_slice(&[9, 9, 9]);
+
+    // One entry covering the ledger's own blocks
+    assert_eq!(
+        ledger_archives(ledger, &Nat::from(0), &Nat::from(42), None),
+        vec![ArchiveInfo {
+            canister_id: ledger,
+            start: Nat::from(0),
+            end: Nat::from(41),
+        }]
+    );
+
+    // Nothing before the first block, and nothing after the ledger itself
+    assert!(ledger_archives(ledger, &Nat::from(0), &Nat::from(0), None).is_empty());
+    assert!(ledger_archives(ledger, &Nat::from(0), &Nat::from(42), Some(ledger)).is_empty());
+}
+
+#[test]
+fn test_spendable_via() {
+    let now = 1_000;
+    let balance = Nat::from(300_000);
+    let allowance = Allowance {
+        allowance: Nat::from(500_000),
+        expires_at: Some(2_000),
+    };
+
+    // The spendable amount is bounded by the smaller of balance and allowance
+    let (balance, allowed) = (balance, allowance.clone().effective(now).allowance);
+    assert_eq!(allowed, Nat::from(500_000));
+    assert_eq!(std::cmp::min(balance.clone(), allowed), Nat::from(300_000));
+
+    // Once the allowance expires nothing is spendable
+    let allowed = allowance.effective(3_000).allowance;
+    assert_eq!(std::cmp::min(balance, allowed), Nat::from(0));
+}
+
+#[test]
+fn test_icrc3_supported_block_types() {
+    let block_types: Vec<String> = supported_block_types().into_iter().map(|t| t.block_type).collect();
+    let emitted = [
+        "mint", "burn", "xfer", "approve", "revoke", "finalize", "reverse", "fee", "fee_change", "genesis",
+        "daily_summary",
+    ];
+    for op in emitted {
+        assert!(block_types.contains(&op.to_string()));
+    }
+}
+
+#[test]
+fn test_deferred_fee_settlement() {
+    let mut data = default_token_data();
+    let fee = Nat::from(10_000);
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let bob = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+
+    // Fees are charged per transfer unless a debt cap is set
+    assert_eq!(data.accrue_fee_debt(&Nat::from(0), &fee), Ok(None));
+    data.fee_debt_cap = Some(Nat::from(25_000));
+
+    // Two transfers accrue their fees as debt; the third would exceed the cap
+    let debt = data.accrue_fee_debt(&Nat::from(0), &fee).unwrap().unwrap();
+    let debt = data.accrue_fee_debt(&debt, &fee).unwrap().unwrap();
+    assert_eq!(debt, Nat::from(20_000));
+    assert!(data.accrue_fee_debt(&debt, &fee).is_err());
+
+    // Deferred transfers move the full amount; the settlement block debits the debt once
+    let transfer = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(100_000),
+            from: alice.clone(),
+            to: bob.clone(),
+            spender: None,
+            memo: None,
+            fee: Some(Nat::from(0)),
+            created_at_time: None,
+            category: None,
+        },
+        1,
+    );
+    let settlement = Transaction::fee_payment(FeePayment { from: alice.clone(), amount: debt }, 2);
+    assert_eq!(settlement.kind, "fee");
+    assert_eq!(transfer.balance_delta(&alice) * 2 + settlement.balance_delta(&alice), Int::from(-220_000));
+    assert!(settlement.accounts().contains(&alice));
+    assert!(!settlement.accounts().contains(&bob));
+
+    // Once settled the account starts again from zero debt
+    assert_eq!(data.accrue_fee_debt(&Nat::from(0), &fee), Ok(Some(fee)));
+}
+
+#[test]
+fn test_block_phash_chain() {
+    // Reference hashes from the ICRC-3 specification
+    assert_eq!(
+        hex::encode(hash_value(&Value::Nat(Nat::from(42)))),
+        "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+    );
+    assert_eq!(
+        hex::encode(hash_value(&Value::Text("Hello, World!".to_string()))),
+        "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+    );
+    assert_eq!(
+        hex::encode(hash_value(&Value::Blob(vec![1, 2, 3, 4]))),
+        "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+    );
+    assert_eq!(
+        hex::encode(hash_value(&Value::Array(vec![
+            Value::Nat(Nat::from(3)),
+            Value::Text("foo".to_string()),
+            Value::Blob(vec![5, 6]),
+        ]))),
+        "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+    );
+
+    // Map hashes do not depend on entry order
+    let a = ("a".to_string(), Value::Nat(Nat::from(1)));
+    let b = ("b".to_string(), Value::Text("x".to_string()));
+    assert_eq!(
+        hash_value(&Value::Map(vec![a.clone(), b.clone()])),
+        hash_value(&Value::Map(vec![b, a]))
+    );
+
+    // Record three blocks, each carrying the hash of the one before it
+    let mut blocks: Vec<Value> = Vec::new();
+    let mut last_hash: Option<[u8; 32]> = None;
+    for ts in 1..=3u64 {
+        let mut map = vec![("ts".to_string(), Value::Nat64(ts))];
+        if let Some(phash) = last_hash {
+            map.push(("phash".to_string(), Value::Blob(phash.to_vec())));
+        }
+        map.push(("op".to_string(), Value::Text("mint".to_string())));
+        let block = Value::Map(map);
+        last_hash = Some(hash_value(&block));
+        blocks.push(block);
+    }
+
+    for pair in blocks.windows(2) {
+        let phash = match &pair[1] {
+            Value::Map(entries) => entries.iter().find(|(key, _)| key == "phash").map(|(_, value)| value.clone()),
+            _ => None,
+        };
+        assert_eq!(phash, Some(Value::Blob(hash_value(&pair[0]).to_vec())));
+    }
+}
+
+#[test]
+fn test_stable_layout() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut balances: StableBTreeMap<Account, StableNat, _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let mut allowances: StableBTreeMap<AccountPair, Allowance, _> = StableBTreeMap::init(manager.get(MemoryId::new(1)));
+    let mut transactions: StableBTreeMap<StableBlockIndex, u64, _> = StableBTreeMap::init(manager.get(MemoryId::new(2)));
+
+    for id in 0..50u8 {
+        let account = Account {
+            owner: Principal::from_slice(&[id]),
+            subaccount: None,
+        };
+        balances.insert(account.clone(), StableNat::from(1_000u64));
+        allowances.insert(
+            AccountPair(account.clone(), account),
+            Allowance { allowance: Nat::from(10), expires_at: None },
+        );
+    }
+    for index in 0..500 {
+        transactions.insert(StableBlockIndex(index), index);
+    }
+
+    // The three known memories are listed, each holding at least one page
+    let layout = memory_layout(&manager);
+    let ids: Vec<u8> = layout.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, vec![0, 1, 2]);
+    assert!(layout.iter().all(|(_, pages)| *pages >= 1));
+}
+
+#[test]
+fn test_init_args() {
+    let mut data = default_token_data();
+    data.apply_init_args(&InitArgs {
+        name: Some("My Token".to_string()),
+        fee: Some(Nat::from(1_000)),
+        ..InitArgs::default()
+    });
+
+    // Supplied values replace the defaults; the rest are kept
+    assert_eq!(data.name, "My Token");
+    assert_eq!(data.fee, Nat::from(1_000));
+    assert_eq!(data.symbol, default_token_data().symbol);
+    assert_eq!(data.decimals, default_token_data().decimals);
+    assert_eq!(data.minting_account, default_token_data().minting_account);
+}
+
+#[test]
+fn test_batch_transfer_results() {
+    let token_data = default_token_data();
+    let mut balance = Nat::from(150_000);
+    let batch = [Nat::from(100_000), Nat::from(100_000), Nat::from(30_000)];
+
+    // Simulate per-entry processing against the running balance; a failure does not undo earlier entries
+    let mut next_block = 0u64;
+    let mut results = Vec::new();
+    for amount in batch {
+        let total = amount + token_data.fee.clone();
+        if balance < total {
+            results.push(TransferResult::Err(TransferError::InsufficientFunds { balance: balance.clone() }));
+            continue;
+        }
+        balance -= total;
+        results.push(TransferResult::Ok(Nat::from(next_block)));
+        next_block += 1;
+    }
+
+    assert_eq!(results[0], TransferResult::Ok(Nat::from(0)));
+    assert_eq!(
+        results[1],
+        TransferResult::Err(TransferError::InsufficientFunds { balance: Nat::from(40_000) })
+    );
+    assert_eq!(results[2], TransferResult::Ok(Nat::from(1)));
+    assert_eq!(balance, Nat::from(0));
+}
+
+#[test]
+fn test_escrow_release_and_refund() {
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let recipient = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let stranger = Principal::from_slice(&[7, 8, 9]);
+    let escrow = Escrow {
+        from: sender.clone(),
+        to: recipient.clone(),
+        amount: Nat::from(100_000),
+        expires_at: 5_000,
+    };
+
+    // Only the sender can release
+    assert!(escrow.can_release(sender.owner));
+    assert!(!escrow.can_release(recipient.owner));
+    assert!(!escrow.can_release(stranger));
+
+    // Escrowed funds sit in a ledger-owned account, so the lock and the release are ordinary transfers
+    let ledger = Principal::from_slice(&[10, 11, 12]);
+    let holder = ledger_account(ledger, ESCROW_TAG);
+    assert_eq!(holder.subaccount.as_deref().map(|subaccount| &subaccount[..6]), Some(&b"escrow"[..]));
+    assert_ne!(holder.clone().canonical(), Account { owner: ledger, subaccount: None });
+    let transfer = |from: &Account, to: &Account| Transfer {
+        amount: escrow.amount.clone(),
+        from: from.clone(),
+        to: to.clone(),
+        spender: None,
+        memo: None,
+        fee: None,
+        created_at_time: None,
+        category: None,
+    };
+    let lock = Transaction::transfer(transfer(&sender, &holder), 1_000);
+    assert_eq!(lock.balance_delta(&recipient), Int::from(0));
+    assert_eq!(lock.balance_delta(&holder), Int::from(100_000));
+    let release = Transaction::transfer(transfer(&holder, &recipient), 2_000);
+    assert_eq!(release.balance_delta(&recipient), Int::from(100_000));
+    assert_eq!(release.balance_delta(&holder), Int::from(-100_000));
+
+    // The sender can reclaim an expired escrow; the recipient can refund at any time
+    assert!(!escrow.can_refund(sender.owner, 4_999));
+    assert!(escrow.can_refund(sender.owner, 5_000));
+    assert!(escrow.can_refund(recipient.owner, 1_000));
+    assert!(!escrow.can_refund(stranger, 10_000));
+
+    let refund = Transaction::transfer(transfer(&holder, &sender), 6_000);
+    assert_eq!(refund.balance_delta(&sender), Int::from(100_000));
+}
+
+#[test]
+fn test_account_activity_bounds() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut index: StableBTreeMap<AccountBlock, (), _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[id]),
+        subaccount: None,
+    };
+    let transfer = |from: Account, to: Account| Transfer {
+        amount: Nat::from(10),
+        from,
+        to,
+        spender: None,
+        memo: None,
+        fee: None,
+        created_at_time: None,
+        category: None,
+    };
+
+    // Block 0 mints to A, 1 moves B -> C, 2 moves A -> B, 3 moves C -> B
+    let blocks = vec![
+        Transaction::mint(Mint { amount: Nat::from(100), to: account(1), memo: None, created_at_time: None, fee: None }, 0),
+        Transaction::transfer(transfer(account(2), account(3)), 1),
+        Transaction::transfer(transfer(account(1), account(2)), 2),
+        Transaction::transfer(transfer(account(3), account(2)), 3),
+    ];
+    for (block, tx) in blocks.iter().enumerate() {
+        index_account_blocks(&mut index, tx, block as u64);
+    }
+
+    assert_eq!(account_block_bounds(&index, &account(1)), Some((0, 2)));
+    assert_eq!(account_block_bounds(&index, &account(2)), Some((1, 3)));
+    assert_eq!(account_block_bounds(&index, &account(3)), Some((1, 3)));
+    assert_eq!(account_block_bounds(&index, &account(4)), None);
+}
+
+#[test]
+fn test_account_transactions_index() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut index: StableBTreeMap<AccountBlock, (), _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let a = Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: None,
+    };
+    let b = Account {
+        owner: Principal::from_slice(&[2]),
+        subaccount: None,
+    };
+
+    let mint = Transaction::mint(
+        Mint { amount: Nat::from(100), to: a.clone(), memo: None, created_at_time: None, fee: None },
+        0,
+    );
+    let transfer = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(40),
+            from: a.clone(),
+            to: b.clone(),
+            spender: None,
+            memo: None,
+            fee: None,
+            created_at_time: None,
+            category: None,
+        },
+        1,
+    );
+    index_account_blocks(&mut index, &mint, 0);
+    index_account_blocks(&mut index, &transfer, 1);
+
+    assert_eq!(account_blocks(&index, &a, 0, 10), vec![0, 1]);
+    assert_eq!(account_blocks(&index, &b, 0, 10), vec![1]);
+
+    // Paging by start block and length
+    assert_eq!(account_blocks(&index, &a, 1, 10), vec![1]);
+    assert_eq!(account_blocks(&index, &a, 0, 1), vec![0]);
+    assert!(account_blocks(&index, &b, 2, 10).is_empty());
+}
+
+#[test]
+fn test_transfer_tip() {
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let recipient = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let tip_account = Account {
+        owner: Principal::from_slice(&[9, 9, 9]),
+        subaccount: None,
+    };
+    let mut tx = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(100_000),
+            from: sender.clone(),
+            to: recipient.clone(),
+            spender: None,
+            memo: None,
+            fee: Some(Nat::from(10_000)),
+            created_at_time: None,
+            category: None,
+        },
+        1_000,
+    );
+
+    // The tip account grows by the tip, paid by the sender on top of amount and fee
+    tx.tip = Some(Tip { amount: Nat::from(500), to: Some(tip_account.clone()) });
+    assert_eq!(tx.balance_delta(&tip_account), Int::from(500));
+    assert_eq!(tx.balance_delta(&sender), Int::from(-110_500));
+    assert_eq!(tx.balance_delta(&recipient), Int::from(100_000));
+    assert!(tx.accounts().contains(&tip_account));
+    assert_eq!(tx.supply_change(), (Nat::from(0), Nat::from(0)));
+
+    // Without a tip account the tip is burned
+    tx.tip = Some(Tip { amount: Nat::from(500), to: None });
+    assert_eq!(tx.supply_change(), (Nat::from(0), Nat::from(500)));
+    assert_eq!(tx.balance_delta(&sender), Int::from(-110_500));
+}
+
+#[test]
+fn test_zero_amount_rejected() {
+    // Zero amounts are rejected before any balance, supply or block changes
+    assert_eq!(validate_amount(&Nat::from(0)), Err("amount must be positive".to_string()));
+    assert_eq!(validate_amount(&Nat::from(1)), Ok(()));
+    assert_eq!(ErrorCode::from_code(17), Some(ErrorCode::InvalidAmount));
+    assert_eq!(ErrorCode::InvalidAmount.to_nat(), Nat::from(17));
+}
+
+#[test]
+fn test_icrc3_self_check() {
+    let block = |phash: Option<[u8; 32]>, amount: u64| {
+        let mut entries = vec![("amt".to_string(), Value::Nat(Nat::from(amount)))];
+        if let Some(phash) = phash {
+            entries.push(("phash".to_string(), Value::Blob(phash.to_vec())));
+        }
+        Value::Map(entries)
+    };
+    let first = block(None, 10);
+    let second = block(Some(hash_value(&first)), 20);
+    let third = block(Some(hash_value(&second)), 30);
+    let tip = hash_value(&third);
+
+    // A healthy chain links every block and ends at the recorded tip
+    let chain = vec![first.clone(), second.clone(), third.clone()];
+    assert!(verify_chain_tail(&chain, Some(&tip)));
+    assert!(verify_chain_tail(&[], None));
+
+    // Tampering with a middle block breaks its successor's phash
+    let tampered = vec![first, block(Some(hash_value(&chain[0])), 21), third.clone()];
+    assert!(!verify_chain_tail(&tampered, Some(&tip)));
+
+    // Tampering with the newest block no longer matches the tip
+    let tampered_tip = vec![chain[0].clone(), second.clone(), block(Some(hash_value(&second)), 31)];
+    assert!(!verify_chain_tail(&tampered_tip, Some(&tip)));
+    assert!(!verify_chain_tail(&[], Some(&tip)));
+}
+
+#[test]
+fn test_burn_supply_underflow() {
+    // An account holding more than the total supply is inconsistent state; burning it must not trap
+    let mut data = TokenData {
+        total_supply: Nat::from(50),
+        ..default_token_data()
+    };
+
+    let result = data.reduce_supply(&Nat::from(100));
+    assert!(matches!(
+        result,
+        Err(TransferError::GenericError { ref error_code, .. }) if *error_code == ErrorCode::SupplyUnderflow.to_nat()
+    ));
+    assert_eq!(data.total_supply, Nat::from(50));
+
+    assert!(data.reduce_supply(&Nat::from(50)).is_ok());
+    assert_eq!(data.total_supply, Nat::from(0));
+}
+
+#[test]
+fn test_account_aliases() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut aliases: StableBTreeMap<Alias, Account, _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let shop = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: Some(vec![1; 32]),
+    };
+
+    // Register an alias for the shop's subaccount
+    let alias = Alias::new("coffee-shop".to_string()).unwrap();
+    assert_eq!(Alias::check_claim(aliases.get(&alias).as_ref(), shop.owner), Ok(()));
+    aliases.insert(alias.clone(), shop.clone());
+
+    // A transfer to the alias lands in the mapped account
+    let to = aliases.get(&Alias("coffee-shop".to_string())).unwrap();
+    let tx = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(2_500),
+            from: sender.clone(),
+            to,
+            spender: None,
+            memo: None,
+            fee: Some(Nat::from(10)),
+            created_at_time: None,
+            category: None,
+        },
+        1_000,
+    );
+    assert_eq!(tx.balance_delta(&shop), Int::from(2_500));
+
+    // Another principal cannot claim it; the holder can re-point it
+    assert_eq!(
+        Alias::check_claim(aliases.get(&alias).as_ref(), sender.owner),
+        Err("Alias is already taken".to_string())
+    );
+    assert_eq!(Alias::check_claim(aliases.get(&alias).as_ref(), shop.owner), Ok(()));
+
+    // Malformed aliases are rejected
+    assert!(Alias::new(String::new()).is_err());
+    assert!(Alias::new("Coffee Shop".to_string()).is_err());
+    assert!(Alias::new("a".repeat(MAX_ALIAS_LENGTH + 1)).is_err());
+}
+
+#[test]
+fn test_stable_nat_compact_encoding() {
+    let above_u128: Nat = "340282366920938463463374607431768211456000".parse().unwrap();
+    for value in [Nat::from(0), Nat::from(u64::MAX), above_u128] {
+        let stable = StableNat::from_nat(value.clone());
+        let bytes = stable.to_bytes();
+        assert_eq!(StableNat::from_bytes(bytes).as_nat(), &value);
+    }
+
+    // A u64 takes a length byte plus eight bytes instead of twenty decimal digits
+    assert_eq!(StableNat::from_nat(Nat::from(u64::MAX)).to_bytes().len(), 9);
+
+    // Values stored in the old decimal-string encoding still decode
+    let legacy = std::borrow::Cow::Owned(b"18446744073709551615".to_vec());
+    assert_eq!(StableNat::from_bytes(legacy).as_nat(), &Nat::from(u64::MAX));
+    let legacy_zero = std::borrow::Cow::Owned(b"0".to_vec());
+    assert_eq!(StableNat::from_bytes(legacy_zero).as_nat(), &Nat::from(0));
+}
+
+#[test]
+fn test_fee_collector() {
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let recipient = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let collector = Account {
+        owner: Principal::from_slice(&[9, 9, 9]),
+        subaccount: None,
+    };
+    let transfer = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(100_000),
+            from: sender.clone(),
+            to: recipient.clone(),
+            spender: None,
+            memo: None,
+            fee: Some(Nat::from(10_000)),
+            created_at_time: None,
+            category: None,
+        },
+        1_000,
+    );
+
+    // The collector's balance grows by exactly the fee
+    let collected = Transaction {
+        fee_collector: Some(collector.clone()),
+        ..transfer.clone()
+    };
+    assert_eq!(collected.fee(), Nat::from(10_000));
+    assert_eq!(collected.balance_delta(&collector), Int::from(10_000));
+    assert_eq!(collected.balance_delta(&sender), Int::from(-110_000));
+    assert!(collected.accounts().contains(&collector));
+
+    // Without a collector nobody is credited with the fee
+    assert_eq!(transfer.balance_delta(&collector), Int::from(0));
+    assert_eq!(transfer.balance_delta(&recipient), Int::from(100_000));
+
+    // A settled fee debt goes to the collector as well
+    let settled = Transaction {
+        fee_collector: Some(collector.clone()),
+        ..Transaction::fee_payment(FeePayment { from: sender, amount: Nat::from(30_000) }, 2_000)
+    };
+    assert_eq!(settled.balance_delta(&collector), Int::from(30_000));
+}
+
+#[test]
+fn test_supply_timeseries() {
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let mint = |amount: u64, timestamp: u64| {
+        Transaction::mint(
+            Mint { amount: Nat::from(amount), to: alice.clone(), memo: None, created_at_time: None, fee: None },
+            timestamp,
+        )
+    };
+    let burn = |amount: u64, timestamp: u64| {
+        Transaction::burn(
+            Burn { amount: Nat::from(amount), from: alice.clone(), spender: None, memo: None, created_at_time: None },
+            timestamp,
+        )
+    };
+
+    let day_start = 10 * NANOS_PER_DAY;
+    let log = vec![
+        mint(999, day_start - 1),
+        mint(1_000, day_start),
+        burn(200, day_start + 5),
+        mint(300, day_start + NANOS_PER_DAY),
+        burn(50, day_start + 2 * NANOS_PER_DAY - 1),
+        mint(777, day_start + 2 * NANOS_PER_DAY),
+    ];
+
+    assert_eq!(
+        supply_by_day(log.into_iter(), day_start, 2),
+        vec![
+            (day_start, Nat::from(1_000), Nat::from(200)),
+            (day_start + NANOS_PER_DAY, Nat::from(300), Nat::from(50)),
+        ]
+    );
+    assert!(supply_by_day(std::iter::empty(), day_start, 0).is_empty());
+}
+
+#[test]
+fn test_default_approval_expiry() {
+    let now = 1_000_000;
+    let data = TokenData {
+        default_approval_expiry: Some(3_600_000_000_000),
+        ..default_token_data()
+    };
+
+    // An approval without an expiry is stored with now plus the default
+    assert_eq!(data.approval_expiry(None, now), Some(now + 3_600_000_000_000));
+
+    // An explicit expiry is kept as is
+    assert_eq!(data.approval_expiry(Some(5_000_000), now), Some(5_000_000));
+
+    // Without a default, approvals stay unbounded
+    assert_eq!(default_token_data().approval_expiry(None, now), None);
+}
+
+#[test]
+fn test_account_statement() {
+    let alice = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let bob = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let transfer = |from: &Account, to: &Account, amount: u64, timestamp: u64| {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(amount),
+                from: from.clone(),
+                to: to.clone(),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10)),
+                created_at_time: None,
+                category: None,
+            },
+            timestamp,
+        )
+    };
+
+    let log = vec![
+        transfer(&bob, &alice, 5_000, 100),
+        transfer(&bob, &alice, 1_000, 200),
+        transfer(&alice, &bob, 300, 250),
+        transfer(&alice, &bob, 400, 400),
+    ];
+    let (from_ts, to_ts) = (150, 300);
+    let in_window = log.into_iter().filter(|tx| tx.timestamp >= from_ts && tx.timestamp <= to_ts);
+
+    // Only the transfers at 200 and 250 are counted
+    assert_eq!(
+        Statement::for_account(&alice, in_window),
+        Statement {
+            received: Nat::from(1_000),
+            sent: Nat::from(300),
+            fees_paid: Nat::from(10),
+            net_change: Int::from(690),
+        }
+    );
+}
+
+#[test]
+fn test_adjust_allowance() {
+    let allowance = |amount: u64| Allowance {
+        allowance: Nat::from(amount),
+        expires_at: None,
+    };
+
+    // Two interleaved increases both land, each applied to the allowance the other left behind
+    let after_first = Allowance {
+        allowance: allowance(100).adjusted(&Nat::from(50), true),
+        expires_at: None,
+    };
+    assert_eq!(after_first.adjusted(&Nat::from(30), true), Nat::from(180));
+
+    // A decrease below zero clamps instead of underflowing
+    assert_eq!(allowance(180).adjusted(&Nat::from(500), false), Nat::from(0));
+    assert_eq!(allowance(180).adjusted(&Nat::from(80), false), Nat::from(100));
+}
+
+#[test]
+fn test_min_reputation() {
+    let data = TokenData {
+        min_reputation: Some(10),
+        ..default_token_data()
+    };
+
+    // A low reputation blocks the account's transfers until it is raised
+    assert!(data.below_min_reputation(Reputation(5)));
+    assert!(data.below_min_reputation(Reputation(-3)));
+    assert!(!data.below_min_reputation(Reputation(10)));
+    assert_eq!(ErrorCode::from_code(20), Some(ErrorCode::ReputationTooLow));
+
+    // Without a minimum nobody is blocked
+    assert!(!default_token_data().below_min_reputation(Reputation(i32::MIN)));
+
+    // Scores survive a round trip through stable memory
+    assert_eq!(Reputation::from_bytes(Reputation(-42).to_bytes()), Reputation(-42));
+}
+
+#[test]
+fn test_default_subaccount_normalization() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut balances: StableBTreeMap<Account, StableNat, _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let owner = Principal::from_slice(&[1, 2, 3]);
+    let implicit = Account { owner, subaccount: None };
+    let explicit = Account { owner, subaccount: Some(vec![0; 32]) };
+
+    // Minting to the absent subaccount is visible through the all-zero one once it is canonicalized
+    assert_eq!(explicit.clone().canonical(), implicit);
+    balances.insert(implicit.clone(), StableNat::from(500u64));
+    assert_eq!(balances.get(&explicit.clone().canonical()), Some(StableNat::from(500u64)));
+
+    // Entries stored under the all-zero subaccount before canonicalization are merged into the default account
+    balances.insert(explicit.clone(), StableNat::from(700u64));
+    assert_eq!(balances.len(), 2);
+    merge_into_canonical_keys(&mut balances, |account| account.clone().canonical(), |a, b| a + b);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances.get(&implicit), Some(StableNat::from(1_200u64)));
+    assert!(balances.get(&explicit).is_none());
+
+    // Blocks recorded with either form count towards the same account
+    let transfer = Transfer {
+        amount: Nat::from(100u64),
+        from: Account { owner: Principal::from_slice(&[4, 5, 6]), subaccount: None },
+        to: explicit.clone(),
+        spender: None,
+        memo: None,
+        fee: None,
+        created_at_time: None,
+        category: None,
+    };
+    let tx = Transaction::transfer(transfer, 0);
+    assert_eq!(tx.balance_change(&implicit), (Nat::from(100u64), Nat::from(0u64)));
+    assert!(tx.accounts().contains(&implicit));
+
+    // Other subaccounts stay distinct
+    let other = Account { owner, subaccount: Some(vec![1; 32]) };
+    assert_eq!(other.clone().canonical(), other);
+    assert!(balances.get(&other).is_none());
+}
+
+#[test]
+fn test_canonicalize_values() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut aliases: StableBTreeMap<u64, Account, _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let owner = Principal::from_slice(&[1, 2, 3]);
+    aliases.insert(1, Account { owner, subaccount: Some(vec![0; 32]) });
+    aliases.insert(2, Account { owner, subaccount: Some(vec![1; 32]) });
+
+    canonicalize_values(&mut aliases, |account| account.clone().canonical());
+    assert_eq!(aliases.get(&1), Some(Account { owner, subaccount: None }));
+    assert_eq!(aliases.get(&2), Some(Account { owner, subaccount: Some(vec![1; 32]) }));
+}
+
+#[test]
+fn test_transfer_detailed() {
+    let sender = Account {
+        owner: Principal::from_slice(&[1, 2, 3]),
+        subaccount: None,
+    };
+    let recipient = Account {
+        owner: Principal::from_slice(&[4, 5, 6]),
+        subaccount: None,
+    };
+    let tx = Transaction::transfer(
+        Transfer {
+            amount: Nat::from(100_000),
+            from: sender.clone(),
+            to: recipient.clone(),
+            spender: None,
+            memo: None,
+            fee: Some(Nat::from(10_000)),
+            created_at_time: None,
+            category: None,
+        },
+        1_000,
+    );
+
+    // The post-balances are what a later balance query sees after applying the block
+    let balance_after = |before: u64, account: &Account| Int::from(before) + tx.balance_delta(account);
+    let detailed = DetailedTransfer {
+        block_index: Nat::from(7),
+        from_balance_after: Nat::from(390_000),
+        to_balance_after: Nat::from(150_000),
+        fee_charged: tx.fee(),
+    };
+    assert_eq!(Int::from(detailed.from_balance_after.clone()), balance_after(500_000, &sender));
+    assert_eq!(Int::from(detailed.to_balance_after.clone()), balance_after(50_000, &recipient));
+    assert_eq!(detailed.fee_charged, Nat::from(10_000));
+
+    let result: DetailedTransferResult = Ok(detailed.clone());
+    let bytes = candid::encode_one(&result).unwrap();
+    assert_eq!(candid::decode_one::<DetailedTransferResult>(&bytes).unwrap(), Ok(detailed));
+}
+
+#[test]
+fn test_fee_change_block() {
+    let mut data = default_token_data();
+    let old_fee = std::mem::replace(&mut data.fee, Nat::from(25_000));
+    let tx = Transaction::fee_change(
+        FeeChange {
+            old_fee: old_fee.clone(),
+            new_fee: data.fee.clone(),
+        },
+        1_000,
+    );
+
+    // The new fee is what icrc1_fee reports, and the block records the change without moving funds
+    assert_eq!(data.config().fee, Nat::from(25_000));
+    assert_eq!(tx.kind, "fee_change");
+    assert_eq!(tx.fee_change.as_ref().map(|change| change.old_fee.clone()), Some(old_fee));
+    assert!(tx.accounts().is_empty());
+    assert_eq!(tx.fee(), Nat::from(0));
+    assert_eq!(tx.supply_change(), (Nat::from(0), Nat::from(0)));
+}
+
+#[test]
+fn test_block_request_cap() {
+    // Blocks [0, 100) are archived and [100, 200) are stored locally
+    let (first_local, log_length) = (100, 200);
+
+    // A range spanning the boundary shares the cap between both parts
+    assert_eq!(
+        split_block_request(90, 30, first_local, log_length, Some(15)),
+        (Some((90, 10)), Some((100, 5)))
+    );
+    assert_eq!(
+        split_block_request(90, 30, first_local, log_length, Some(5)),
+        (Some((90, 5)), None)
+    );
+    assert_eq!(
+        split_block_request(90, 30, first_local, log_length, None),
+        (Some((90, 10)), Some((100, 20)))
+    );
+
+    // Requests are clipped to the log
+    assert_eq!(split_block_request(190, 50, first_local, log_length, None), (None, Some((190, 10))));
+    assert_eq!(split_block_request(250, 5, first_local, log_length, None), (None, None));
+    assert_eq!(split_block_request(u64::MAX, u64::MAX, first_local, log_length, Some(10)), (None, None));
+}
+
+#[test]
+fn test_standards_status() {
+    let status = standards_status(|method| method == "icrc2_approve");
+    let enabled = |standard: &str| status.iter().find(|(name, _)| name == standard).unwrap().1;
+
+    // Disabling one ICRC-2 method disables the standard but leaves ICRC-1 alone
+    assert!(!enabled("ICRC-2"));
+    assert!(enabled("ICRC-1"));
+    assert!(enabled("ICRC-3"));
+
+    // Standards this ledger does not implement are never reported as enabled
+    assert!(!enabled("ICRC-10"));
+    assert!(!enabled("ICRC-21"));
+    assert!(standards_status(|_| false).iter().filter(|(_, on)| *on).count() == SUPPORTED_STANDARDS.len());
+}
+
+#[test]
+fn test_mint_deduplication() {
+    let minting_account = Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: None,
+    };
+    let to = Account {
+        owner: Principal::from_slice(&[2]),
+        subaccount: None,
+    };
+    let amount = Nat::from(500u64);
+
+    // Replays the mint path: a known key returns the original block, anything else mints
+    let mut recent = std::collections::BTreeMap::new();
+    let mut supply = Nat::from(0u64);
+    let mut next_block = 0u64;
+    let mut mint = |created_at_time: u64, memo: Option<Vec<u8>>| {
+        let key = DedupKey::mint(created_at_time, &minting_account, &to, &amount, memo);
+        if let Some(block) = recent.get(&key) {
+            return *block;
+        }
+        supply += amount.clone();
+        recent.insert(key, next_block);
+        next_block += 1;
+        next_block - 1
+    };
+
+    assert_eq!(mint(1_000, Some(vec![1])), 0);
+    assert_eq!(mint(1_000, Some(vec![1])), 0);
+    assert_eq!(mint(1_000, Some(vec![2])), 1);
+    assert_eq!(supply, Nat::from(1_000u64));
+
+    // Mints never collide with transfers carrying the same fields
+    let key = DedupKey::mint(1_000, &minting_account, &to, &amount, None);
+    assert_eq!(key.kind, "mint");
+    assert_eq!(key.fee, Nat::from(0u64));
+}
+
+#[test]
+fn test_structured_memo() {
+    let entries = vec![
+        ("order".to_string(), Value::Nat64(42)),
+        ("ref".to_string(), Value::Text("inv-7".to_string())),
+        ("adj".to_string(), Value::Int(Int::from(-3))),
+    ];
+    let memo = encode_structured_memo(&entries).unwrap();
+    assert!(validate_memo(Some(&memo)).is_ok());
+
+    // The encoding ignores entry order, and a block carrying it decodes back sorted by key
+    let mut reversed = entries.clone();
+    reversed.reverse();
+    assert_eq!(encode_structured_memo(&reversed).unwrap(), memo);
+    let to = Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: None,
+    };
+    let block = Transaction::mint(
+        Mint {
+            amount: Nat::from(1u64),
+            to,
+            memo: Some(memo),
+            created_at_time: None,
+            fee: None,
+        },
+        0,
+    );
+    let mut sorted = entries;
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(decode_structured_memo(block.memo().unwrap()), Some(sorted));
+
+    // Opaque memos, duplicate keys and oversized entries are rejected
+    assert_eq!(decode_structured_memo(&[1, 2, 3]), None);
+    let duplicate = vec![("a".to_string(), Value::Nat64(1)), ("a".to_string(), Value::Nat64(2))];
+    assert!(encode_structured_memo(&duplicate).is_err());
+    let oversized = vec![("note".to_string(), Value::Text("x".repeat(40)))];
+    assert!(encode_structured_memo(&oversized).is_err());
+}
+
+#[test]
+fn test_block_fee_presence() {
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[id]),
+        subaccount: None,
+    };
+    let transfer = |fee_implicit: bool| {
+        let mut tx = Transaction::transfer(
+            Transfer {
+                amount: Nat::from(100u64),
+                from: account(1),
+                to: account(2),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10u64)),
+                created_at_time: None,
+                category: None,
+            },
+            0,
+        );
+        tx.fee_implicit = Some(fee_implicit);
+        tx
+    };
+
+    // A caller passing fee = None gets a block without a fee field
+    let implicit = transfer(true);
+    assert_eq!(implicit.block_fee(), None);
+    assert_eq!(implicit.fee(), Nat::from(10u64));
+
+    // An explicit, correct fee is recorded in the block
+    let explicit = transfer(false);
+    assert_eq!(explicit.block_fee(), Some(&Nat::from(10u64)));
+
+    // Blocks recorded before the flag existed keep their fee
+    let legacy = Transaction { fee_implicit: None, ..explicit };
+    assert_eq!(legacy.block_fee(), Some(&Nat::from(10u64)));
+}
+
+#[test]
+fn test_get_transaction_lookup() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut transactions: StableBTreeMap<StableBlockIndex, Transaction, _> = StableBTreeMap::init(manager.get(MemoryId::new(2)));
+    for index in 0..3u64 {
+        let mint = Mint {
+            amount: Nat::from(index + 1),
+            to: Account {
+                owner: Principal::from_slice(&[1]),
+                subaccount: None,
+            },
+            memo: None,
+            created_at_time: None,
+            fee: None,
+        };
+        transactions.insert(StableBlockIndex(index), Transaction::mint(mint, index));
+    }
+    let lookup = |index: Nat| StableBlockIndex::try_from_nat(&index).and_then(|index| transactions.get(&index));
+
+    // An existing index returns its block
+    assert_eq!(lookup(Nat::from(1u64)).unwrap().mint.unwrap().amount, Nat::from(2u64));
+
+    // Out-of-range indices return nothing, including ones too large to alias block 0
+    assert!(lookup(Nat::from(3u64)).is_none());
+    let huge: Nat = "18446744073709551616".parse().unwrap();
+    assert!(lookup(huge).is_none());
+}
+
+#[test]
+fn test_largest_transaction() {
+    let mut data = default_token_data();
+    let account = Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: None,
+    };
+    let mint = |amount: u64| {
+        Transaction::mint(
+            Mint {
+                amount: Nat::from(amount),
+                to: account.clone(),
+                memo: None,
+                created_at_time: None,
+                fee: None,
+            },
+            0,
+        )
+    };
+
+    // Increasing sizes move the record forward; smaller ones and non-moving blocks leave it alone
+    for (index, amount) in [(0u64, 10u64), (1, 50), (2, 20), (3, 70)] {
+        data.track_largest_transaction(&Nat::from(index), &mint(amount));
+    }
+    let fee_change = Transaction::fee_change(
+        FeeChange {
+            old_fee: Nat::from(1u64),
+            new_fee: Nat::from(1_000u64),
+        },
+        0,
+    );
+    data.track_largest_transaction(&Nat::from(4u64), &fee_change);
+    assert_eq!(data.largest_transaction, Some((Nat::from(3u64), Nat::from(70u64))));
+
+    // The record is saved with the token data across an upgrade
+    let restored = TokenData::from_bytes(data.to_bytes());
+    assert_eq!(restored.largest_transaction, Some((Nat::from(3u64), Nat::from(70u64))));
+}
+
+#[test]
+fn test_net_transfers() {
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: Some(vec![id; 32]),
+    };
+    let (a, b, c) = (account(1), account(2), account(3));
+    let batch = vec![
+        (a.clone(), b.clone(), Nat::from(100u64)),
+        (b.clone(), a.clone(), Nat::from(30u64)),
+        (a.clone(), c.clone(), Nat::from(50u64)),
+        (c.clone(), b.clone(), Nat::from(20u64)),
+        (b.clone(), c.clone(), Nat::from(20u64)),
+    ];
+
+    // A→B and B→A net to 70 in A's direction, A→C is untouched, and B↔C cancels out
+    let netted = net_transfers(&batch);
+    assert_eq!(netted, vec![(0, Nat::from(70u64)), (2, Nat::from(50u64))]);
+    assert!(netted.len() < batch.len());
+
+    // The larger side wins even when it comes second
+    let reversed = net_transfers(&[
+        (a.clone(), b.clone(), Nat::from(10u64)),
+        (b.clone(), a.clone(), Nat::from(25u64)),
+    ]);
+    assert_eq!(reversed, vec![(1, Nat::from(15u64))]);
+}
+
+#[test]
+fn test_tip_certification() {
+    let mut data = default_token_data();
+    assert_eq!(data.tip(), None);
+
+    // Each recorded block moves the tip, and with it the certified data
+    let mut roots = Vec::new();
+    for block in 0..3u8 {
+        data.next_block_index += 1u64;
+        data.last_block_hash = Some(vec![block; 32]);
+        let (last_block_index, last_block_hash) = data.tip().unwrap();
+        assert_eq!(last_block_index, block as u64);
+        roots.push(tip_tree_root(last_block_index, &last_block_hash));
+    }
+    assert!(roots[0] != roots[1] && roots[1] != roots[2]);
+
+    // The tree handed to clients is the self-described CBOR fork of the two labeled leaves
+    let tree = encode_tip_tree(2, &[2; 32]);
+    assert!(tree.starts_with(&[0xd9, 0xd9, 0xf7, 0x83, 0x01, 0x83, 0x02, 0x4f]));
+    assert!(tree.windows(32).any(|window| window == [2; 32]));
+    assert!(tree.ends_with(&[0x82, 0x03, 0x41, 0x02]));
+}
+
+#[test]
+fn test_max_balance() {
+    use ic_stable_structures::BoundedStorable;
+
+    let max = StableNat::max_value();
+    assert_eq!(StableNat::from_nat(max.clone()).to_bytes().len(), MAX_VALUE_BYTES + 1);
+    assert_eq!(max.clone() + Nat::from(1u64), Nat(Nat::from(2u64).0.pow(256)));
+
+    // A mint up to the cap fits the encoding; one unit more is rejected
+    let mut data = default_token_data();
+    data.total_supply = Nat::from(1_000u64);
+    let room = max.clone() - Nat::from(1_000u64);
+    assert!(!data.mint_exceeds_max_balance(&(room.clone() - Nat::from(1u64))));
+    assert!(!data.mint_exceeds_max_balance(&room));
+    assert!(data.mint_exceeds_max_balance(&(room + Nat::from(1u64))));
+    assert_eq!(ErrorCode::from_code(21), Some(ErrorCode::BalanceOverflow));
+
+    // The largest block the ledger writes still fits the log when every amount in it is at the cap
+    let account = Account {
+        owner: Principal::from_slice(&[0xff; 29]),
+        subaccount: Some(vec![0xff; 32]),
+    };
+    let mut block = Transaction::transfer(
+        Transfer {
+            amount: max.clone(),
+            from: account.clone(),
+            to: account.clone(),
+            spender: Some(account.clone()),
+            memo: Some(vec![0xff; MAX_MEMO_LENGTH]),
+            fee: Some(max.clone()),
+            created_at_time: Some(u64::MAX),
+            category: Some("c".repeat(MAX_CATEGORY_LENGTH)),
+        },
+        u64::MAX,
+    );
+    block.tip = Some(Tip {
+        amount: max,
+        to: Some(account.clone()),
+    });
+    block.fee_collector = Some(account);
+    block.fee_implicit = Some(false);
+    block.phash = Some(vec![0xff; 32]);
+    assert!(block.to_bytes().len() <= Transaction::MAX_SIZE as usize);
+}
+
+#[test]
+fn test_max_supply() {
+    let mut data = default_token_data();
+    data.total_supply = Nat::from(900u64);
+
+    // Without a cap any mint is allowed
+    assert!(!data.exceeds_max_supply(&Nat::from(1_000_000u64)));
+
+    // Under a cap, mints up to it succeed and anything past it is rejected
+    data.max_supply = Some(Nat::from(1_000u64));
+    assert!(!data.exceeds_max_supply(&Nat::from(100u64)));
+    assert!(data.exceeds_max_supply(&Nat::from(101u64)));
+    assert_eq!(data.config().max_supply, Some(Nat::from(1_000u64)));
+}
+
+#[test]
+fn test_daily_summary() {
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+    let account = |id: u8| Account {
+        owner: Principal::from_slice(&[id]),
+        subaccount: None,
+    };
+    let mint = |amount: u64, timestamp: u64| {
+        Transaction::mint(
+            Mint {
+                amount: Nat::from(amount),
+                to: account(1),
+                memo: None,
+                created_at_time: None,
+                fee: None,
+            },
+            timestamp,
+        )
+    };
+    let transfer = |amount: u64, timestamp: u64| {
+        Transaction::transfer(
+            Transfer {
+                amount: Nat::from(amount),
+                from: account(1),
+                to: account(2),
+                spender: None,
+                memo: None,
+                fee: Some(Nat::from(10u64)),
+                created_at_time: None,
+                category: None,
+            },
+            timestamp,
+        )
+    };
+
+    // Disabled by default: nothing is tracked
+    let mut data = default_token_data();
+    assert_eq!(data.track_daily_totals(&mint(1_000, DAY)), None);
+    assert_eq!(data.daily_totals, None);
+
+    // Blocks within a day accumulate; the first block of the next day closes it with a summary
+    data.daily_summaries_enabled = Some(true);
+    assert_eq!(data.track_daily_totals(&mint(1_000, DAY + 5)), None);
+    assert_eq!(data.track_daily_totals(&transfer(300, DAY + 10)), None);
+    let summary = data.track_daily_totals(&transfer(50, 2 * DAY + 1)).unwrap();
+    assert_eq!(summary, DailySummary {
+        day_start: DAY,
+        minted: Nat::from(1_000u64),
+        burned: Nat::from(0u64),
+        transferred: Nat::from(300u64),
+        fees: Nat::from(10u64),
+    });
+
+    // The new day starts with the block that closed the previous one
+    let totals = data.daily_totals.clone().unwrap();
+    assert_eq!(totals.day_start, 2 * DAY);
+    assert_eq!(totals.transferred, Nat::from(50u64));
+
+    // Summary blocks carry no amount, so they leave balances and the largest transaction alone
+    let block = Transaction::daily_summary(summary, 2 * DAY + 1);
+    assert_eq!(block.kind, "daily_summary");
+    assert_eq!(block.amount(), None);
+    assert!(block.accounts().is_empty());
+}
+
+#[test]
+fn test_get_blocks_length_cap() {
+    // A huge length against a small log returns just the log
+    assert_eq!(split_block_request(0, u64::MAX, 0, 5, Some(get_blocks_cap(None))), (None, Some((0, 5))));
+
+    // Against a long log the slice is clamped to the cap
+    assert_eq!(
+        split_block_request(100, u64::MAX, 0, 1_000_000, Some(get_blocks_cap(None))),
+        (None, Some((100, MAX_GET_BLOCKS_LENGTH)))
+    );
+
+    // A configured limit can only lower the cap
+    assert_eq!(get_blocks_cap(Some(50)), 50);
+    assert_eq!(get_blocks_cap(Some(u64::MAX)), MAX_GET_BLOCKS_LENGTH);
+}
+
+#[test]
+fn test_balance_of_batch() {
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+
+    let manager = MemoryManager::init(DefaultMemoryImpl::default());
+    let mut balances: StableBTreeMap<Account, StableNat, _> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+    let owner = Principal::from_slice(&[1]);
+    let account = |subaccount: Option<Vec<u8>>| Account { owner, subaccount };
+    balances.insert(account(None), StableNat::from(100u64));
+    balances.insert(account(Some(vec![2; 32])), StableNat::from(250u64));
+
+    // Balances come back positionally, with unfunded accounts at zero and the all-zero subaccount as the default
+    let balance_of_batch = |accounts: &[Account]| -> Vec<Nat> {
+        accounts
+            .iter()
+            .map(|account| balances.get(&account.clone().canonical()).map(|balance| balance.into_nat()).unwrap_or_else(|| Nat::from(0u64)))
+            .collect()
+    };
+    let queried = [account(Some(vec![2; 32])), account(Some(vec![3; 32])), account(Some(vec![0; 32]))];
+    assert_eq!(balance_of_batch(&queried), vec![Nat::from(250u64), Nat::from(0u64), Nat::from(100u64)]);
+    assert!(balance_of_batch(&[]).is_empty());
+}
+
+#[test]
+fn test_min_burn_amount() {
+    let mut data = default_token_data();
+    let minting_account = Account {
+        owner: Principal::from_slice(&[1]),
+        subaccount: None,
+    };
+    data.minting_account = Some(minting_account.clone());
+    let other = Account {
+        owner: Principal::from_slice(&[9]),
+        subaccount: None,
+    };
+
+    // No minimum by default
+    assert_eq!(data.min_burn_amount(), Nat::from(0u64));
+    assert_eq!(data.check_min_burn(&Nat::from(1u64)), Ok(()));
+
+    // Below the minimum is rejected with the minimum; at the minimum succeeds
+    data.min_burn_amount = Some(Nat::from(1_000u64));
+    assert_eq!(data.check_min_burn(&Nat::from(999u64)), Err(Nat::from(1_000u64)));
+    assert_eq!(data.check_min_burn(&Nat::from(1_000u64)), Ok(()));
+
+    // Transfers only count as burns when they go to the minting account
+    assert_eq!(data.check_min_burn_to(&minting_account, &Nat::from(999u64)), Err(Nat::from(1_000u64)));
+    assert_eq!(data.check_min_burn_to(&other, &Nat::from(999u64)), Ok(()));
+    assert_eq!(data.config().min_burn_amount, Nat::from(1_000u64));
+}